@@ -0,0 +1,229 @@
+use std::sync::OnceLock;
+
+use crate::board::NEIGHBOUR_OFFSETS;
+
+pub const MAX_BOARD_SIZE: i8 = 10;
+
+/// Number of cells on the largest supported board, `3n(n - 1) + 1` for `n = MAX_BOARD_SIZE`.
+pub const MAX_CELLS: usize = 271;
+
+const WORDS: usize = MAX_CELLS.div_ceil(64);
+
+/// Marks a missing neighbour in `Geometry::neighbours`.
+pub const NO_CELL: u16 = u16::MAX;
+
+/// Fixed-size set of cell ids, one bit per cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Bitboard([u64; WORDS]);
+
+impl Bitboard {
+    pub fn contains(&self, cell: usize) -> bool {
+        self.0[cell / 64] & (1 << (cell % 64)) != 0
+    }
+
+    pub fn insert(&mut self, cell: usize) {
+        self.0[cell / 64] |= 1 << (cell % 64);
+    }
+
+    pub fn remove(&mut self, cell: usize) {
+        self.0[cell / 64] &= !(1 << (cell % 64));
+    }
+
+    pub fn toggle(&mut self, cell: usize) {
+        self.0[cell / 64] ^= 1 << (cell % 64);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.0.iter().map(|w| w.count_ones()).sum()
+    }
+
+    pub fn union(&self, other: &Bitboard) -> Bitboard {
+        let mut words = self.0;
+        for (w, o) in words.iter_mut().zip(other.0) {
+            *w |= o;
+        }
+        Bitboard(words)
+    }
+
+    pub fn difference(&self, other: &Bitboard) -> Bitboard {
+        let mut words = self.0;
+        for (w, o) in words.iter_mut().zip(other.0) {
+            *w &= !o;
+        }
+        Bitboard(words)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(i, &word)| {
+            let mut bits = word;
+            std::iter::from_fn(move || {
+                if bits == 0 {
+                    return None;
+                }
+                let bit = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                Some(i * 64 + bit)
+            })
+        })
+    }
+}
+
+/// Precomputed layout of one board size: dense cell ids, their axial coordinates and the
+/// neighbour, corner and edge tables everything else looks up instead of recomputing.
+#[derive(Debug)]
+pub struct Geometry {
+    radius: i32,
+    /// axial coordinates of every cell id
+    pub coords: Vec<(i32, i32)>,
+    /// cell id for each slot of the `(2n - 1) x (2n - 1)` bounding square, `NO_CELL` outside the hexagon
+    index: Vec<u16>,
+    /// neighbour ids in `NEIGHBOUR_OFFSETS` order, `NO_CELL` past the edge
+    pub neighbours: Vec<[u16; 6]>,
+    /// the neighbours of every cell as a set
+    pub neighbour_masks: Vec<Bitboard>,
+    /// bit per corner, zero for every other cell
+    pub corner_bits: Vec<u8>,
+    /// bit per edge side, zero for corners and for every other cell off the edges
+    pub side_bits: Vec<u8>,
+    /// every cell
+    pub all: Bitboard,
+    /// cells on the outer ring of the board
+    pub boundary: Bitboard,
+    /// Zobrist key of a stone on each cell, per player
+    pub zobrist: Vec<[u64; 2]>,
+}
+
+/// Zobrist key toggled in when the second player is to move.
+pub const ZOBRIST_P2_TO_MOVE: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// SplitMix64 step. The keys only need to be well mixed and identical across runs,
+/// so a fixed seed beats pulling in a seeded RNG.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Geometry {
+    /// Shared geometry for `board_size`, built on first use.
+    pub fn for_size(board_size: i8) -> &'static Geometry {
+        static GEOMETRIES: [OnceLock<Geometry>; MAX_BOARD_SIZE as usize] = [const { OnceLock::new() }; MAX_BOARD_SIZE as usize];
+
+        assert!(
+            (2..=MAX_BOARD_SIZE).contains(&board_size),
+            "board size must be between 2 and {}", MAX_BOARD_SIZE
+        );
+
+        GEOMETRIES[board_size as usize - 1].get_or_init(|| Geometry::build(board_size))
+    }
+
+    fn build(board_size: i8) -> Self {
+        let radius = board_size as i32 - 1;
+        let width = 2 * radius + 1;
+
+        let mut coords = vec![];
+        let mut index = vec![NO_CELL; (width * width) as usize];
+
+        // assume the board is a pointy-bottom hex
+        // the tiles are flat-bottom hexes
+        for r in -radius..=radius {
+            for q in -radius..=radius {
+                if (q + r).abs() <= radius {
+                    index[((q + radius) * width + (r + radius)) as usize] = coords.len() as u16;
+                    coords.push((q, r));
+                }
+            }
+        }
+
+        let mut geometry = Self {
+            radius,
+            index,
+            neighbours: vec![],
+            neighbour_masks: vec![],
+            corner_bits: vec![],
+            side_bits: vec![],
+            all: Bitboard::default(),
+            boundary: Bitboard::default(),
+            zobrist: vec![],
+            coords: vec![],
+        };
+
+        let mut seed = board_size as u64;
+
+        let corners = [
+            (-radius, 0),
+            (-radius, radius),
+            (0, -radius),
+            (0, radius),
+            (radius, -radius),
+            (radius, 0),
+        ];
+
+        for (id, &(q, r)) in coords.iter().enumerate() {
+            let s = -q - r;
+
+            let neighbours = NEIGHBOUR_OFFSETS.map(|(dq, dr)| {
+                geometry.cell(q + dq, r + dr).map_or(NO_CELL, |n| n as u16)
+            });
+
+            let mut mask = Bitboard::default();
+            for &n in neighbours.iter().filter(|&&n| n != NO_CELL) {
+                mask.insert(n as usize);
+            }
+
+            geometry.neighbours.push(neighbours);
+            geometry.neighbour_masks.push(mask);
+
+            geometry.corner_bits.push(match corners.iter().position(|&c| c == (q, r)) {
+                Some(i) => 1 << i,
+                None => 0,
+            });
+
+            // corners belong to no side: a fork needs three edge cells proper
+            geometry.side_bits.push(
+                if corners.contains(&(q, r)) { 0 }
+                else if q == -radius { 1 << 0 }
+                else if r == -radius { 1 << 1 }
+                else if s == -radius { 1 << 2 }
+                else if q == radius { 1 << 3 }
+                else if r == radius { 1 << 4 }
+                else if s == radius { 1 << 5 }
+                else { 0 }
+            );
+
+            geometry.zobrist.push([splitmix64(&mut seed), splitmix64(&mut seed)]);
+
+            geometry.all.insert(id);
+            if q.abs() == radius || r.abs() == radius || s.abs() == radius {
+                geometry.boundary.insert(id);
+            }
+        }
+
+        geometry.coords = coords;
+        geometry
+    }
+
+    /// Cell id of an axial coordinate, `None` when it is off the board.
+    pub fn cell(&self, q: i32, r: i32) -> Option<usize> {
+        let width = 2 * self.radius + 1;
+
+        if q.abs() > self.radius || r.abs() > self.radius {
+            return None;
+        }
+
+        match self.index[((q + self.radius) * width + (r + self.radius)) as usize] {
+            NO_CELL => None,
+            id => Some(id as usize),
+        }
+    }
+
+    pub fn neighbours_of(&self, cell: usize) -> impl Iterator<Item = usize> + '_ {
+        self.neighbours[cell].iter().filter(|&&n| n != NO_CELL).map(|&n| n as usize)
+    }
+}
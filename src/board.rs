@@ -1,7 +1,17 @@
-use core::{error, panic};
-use std::{cmp::{max, min}, collections::HashMap, default, io::stdin, string, thread, time::{Duration, Instant}};
+use std::{fmt, io::{stdin, IsTerminal}, path::Path, str::FromStr, time::Instant};
 
-use crate::{mcts, win_detector::{self, WinDetector}};
+use crate::{bitboard::{Bitboard, Geometry, ZOBRIST_P2_TO_MOVE}, clock::TimeControl, groups::Groups, mcts, record::{GameRecord, MoveStats, PlayerInfo}, win_detector::{WinDetector, WinInfo, WinKind}};
+
+/// Axial offsets of the six neighbours of a hex, in circular order so that consecutive
+/// entries (and the last and first) are neighbours of each other as well.
+pub const NEIGHBOUR_OFFSETS: [(i32, i32); 6] = [
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, 1),
+];
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum HexOwner {
@@ -25,144 +35,382 @@ impl From<&Player> for HexOwner {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub struct Hex {
-    pub q: i32,
-    pub r: i32,
-    pub owner: HexOwner 
+impl Player {
+    pub fn index(self) -> usize {
+        match self {
+            Player::P1 => 0,
+            Player::P2 => 1,
+        }
+    }
+
+    pub fn opponent(self) -> Player {
+        match self {
+            Player::P1 => Player::P2,
+            Player::P2 => Player::P1,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Move {
+    Place(i32, i32),
+    /// second player takes over the first stone instead of placing one, see `Rules::swap`
+    Swap,
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Move::Place(q, r) => write!(f, "({}, {})", q, r),
+            Move::Swap => write!(f, "swap"),
+        }
+    }
+}
+
+impl Move {
+    /// Reads the letter-number notation of Little Golem and HavannahGui, or `swap`. Columns
+    /// run from `a` on the left, rows from 1 at the bottom, so `a1` is the bottom-left corner.
+    pub fn from_notation(input: &str, board_size: i8) -> Result<Move, &'static str> {
+        let input = input.trim();
+
+        if input.eq_ignore_ascii_case("swap") {
+            return Ok(Move::Swap);
+        }
+
+        let mut chars = input.chars();
+        let letter = chars.next().filter(char::is_ascii_alphabetic).ok_or("expected a move such as e5")?;
+        let digits = chars.as_str();
+
+        // rows run to 2n - 1, so two digits are enough and anything longer is off the board
+        if digits.is_empty() || digits.len() > 2 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("expected a move such as e5");
+        }
+
+        let number: u8 = digits.parse().map_err(|_| "expected a move such as e5")?;
+        let n = board_size as i32;
+        let q = (letter.to_ascii_lowercase() as u8 - b'a') as i32 - (n - 1);
+        let r = n.checked_sub(number as i32).ok_or("move is out of bounds")?;
+
+        match Geometry::for_size(board_size).cell(q, r) {
+            Some(_) => Ok(Move::Place(q, r)),
+            None => Err("move is out of bounds"),
+        }
+    }
+
+    /// Writes the move in the notation `from_notation` reads.
+    pub fn to_notation(self, board_size: i8) -> String {
+        let n = board_size as i32;
+
+        match self {
+            Move::Place(q, r) => format!("{}{}", (b'a' + (q + n - 1) as u8) as char, n - r),
+            Move::Swap => "swap".to_string(),
+        }
+    }
+}
+
+/// How moves are written for people and other programs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    /// letter and number, as in `e5`
+    #[default]
+    Standard,
+    /// the `(q, r)` coordinates the engine works in
+    Axial,
+}
+
+impl Notation {
+    pub fn format(self, m: Move, board_size: i8) -> String {
+        match self {
+            Notation::Standard => m.to_notation(board_size),
+            Notation::Axial => m.to_string(),
+        }
+    }
+}
+
+/// Parses `swap` or an axial `q r` pair, also written `(q, r)` as `Display` prints it.
+impl FromStr for Move {
+    type Err = &'static str;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+
+        if input.eq_ignore_ascii_case("swap") {
+            return Ok(Move::Swap);
+        }
+
+        let input = input.strip_prefix('(').and_then(|i| i.strip_suffix(')')).unwrap_or(input);
+        let parts: Vec<&str> = input.split(|c: char| c == ',' || c.is_whitespace()).filter(|p| !p.is_empty()).collect();
+
+        match parts[..] {
+            [q, r] => match (q.parse(), r.parse()) {
+                (Ok(q), Ok(r)) => Ok(Move::Place(q, r)),
+                _ => Err("coordinates must be integers"),
+            },
+            _ => Err("expected a move as `q r` or `swap`"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rules {
+    /// lets the second player answer the first move by taking that stone over (pie rule)
+    pub swap: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum GameResult {
+    Win(Player),
+    /// the board filled up without either player completing a structure
+    Draw,
+    Ongoing,
+}
+
+/// The stone that completed the winning structure. The structure itself is rebuilt from it
+/// on demand by `win_info`, keeping the board cheap to clone.
+#[derive(Debug, Clone, Copy)]
+struct Win {
+    player: Player,
+    kind: WinKind,
+    cell: usize,
+}
+
+/// What `undo_move` needs to take a move back.
+#[derive(Debug, Clone, Copy)]
+struct Played {
+    m: Move,
+    cell: usize,
+    /// win state from before the move
+    win: Option<Win>,
+    /// winning cells from before the move
+    threats: [Bitboard; 2],
+    groups_mark: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct BoardState {
-    pub state: HashMap<(i32, i32), Hex>,
+    geometry: &'static Geometry,
+    /// occupied cells per player, indexed by `Player::index`
+    stones: [Bitboard; 2],
     pub board_size: i8,
     pub turn: Player,
+    pub rules: Rules,
+    /// how moves are printed; both notations are always accepted
+    pub notation: Notation,
+    groups: Groups,
+    win: Option<Win>,
+    /// empty cells where a stone of each player would win at once, indexed by `Player::index`
+    threats: [Bitboard; 2],
+    history: Vec<Played>,
+    /// Zobrist hash of the stones and the side to move
+    hash: u64,
 }
 
 impl BoardState {
     pub fn new(board_size: i8) -> Self {
-        let state = BoardState::initialize_state(board_size);
+        Self::with_rules(board_size, Rules::default())
+    }
 
+    pub fn with_rules(board_size: i8, rules: Rules) -> Self {
         Self {
-            state,
+            geometry: Geometry::for_size(board_size),
+            stones: [Bitboard::default(); 2],
             board_size,
             turn: Player::P1,
-            }
+            rules,
+            notation: Notation::default(),
+            groups: Groups::new(),
+            win: None,
+            threats: [Bitboard::default(); 2],
+            history: vec![],
+            hash: 0,
+        }
+    }
+
+    pub fn geometry(&self) -> &'static Geometry {
+        self.geometry
     }
 
-    fn initialize_state(board_size: i8) -> HashMap<(i32, i32), Hex> {
-        let mut state = HashMap::new();
+    pub fn stones(&self, player: Player) -> &Bitboard {
+        &self.stones[player.index()]
+    }
 
-        // assume the board is a pointy-bottom hex
-        // the tiles are flat-bottom hexes
-        let max_qr: i32 = (board_size - 1) as i32;
+    pub fn occupied(&self) -> Bitboard {
+        self.stones[0].union(&self.stones[1])
+    }
 
-        for q in -max_qr..=max_qr {
-            for r in -max_qr..=max_qr {
-                let s = -q - r;
-                if s.abs() <= max_qr {
-                    state.insert((q, r), Hex { q, r, owner: HexOwner::None });
-                }
-            }
+    /// Position hash, kept up to date by every move and undo. Equal positions reached
+    /// through different move orders hash the same.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    fn toggle_stone(&mut self, player: Player, cell: usize) {
+        self.stones[player.index()].toggle(cell);
+        self.hash ^= self.geometry.zobrist[cell][player.index()];
+    }
+
+    pub fn owner(&self, cell: usize) -> HexOwner {
+        if self.stones[0].contains(cell) {
+            HexOwner::P1
+        } else if self.stones[1].contains(cell) {
+            HexOwner::P2
+        } else {
+            HexOwner::None
         }
+    }
 
-        return state;
+    /// Owner of an axial coordinate; off-board cells read as empty.
+    pub fn owner_at(&self, q: i32, r: i32) -> HexOwner {
+        self.geometry.cell(q, r).map_or(HexOwner::None, |cell| self.owner(cell))
     }
 
 
     // ---  API FOR MCTS --- 
     
     pub fn is_hex_in_bounds(&self, q: i32, r: i32) -> bool {
-        let max_qr: i32 = (self.board_size - 1) as i32;
+        self.geometry.cell(q, r).is_some()
+    }
 
-        if q.abs() > max_qr || r.abs() > max_qr { return false; }
-        
-        return true;
+    /// Reads a move in either notation: `e5`, `q r`, `(q, r)` or `swap`.
+    pub fn parse_move(&self, input: &str) -> Result<Move, &'static str> {
+        let input = input.trim();
+
+        match input.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() => Move::from_notation(input, self.board_size),
+            _ => input.parse(),
+        }
     }
 
-    pub fn legal_moves(&self) -> Vec<(i32, i32)> {
-        let mut moves: Vec<(i32, i32)> = vec![];
-        
-        for hex in self.state.values() {
-            if hex.owner == HexOwner::None {
-                moves.push((hex.q, hex.r));
-            }
-        };
+    /// Writes a move in the board's `notation`.
+    pub fn format_move(&self, m: Move) -> String {
+        self.notation.format(m, self.board_size)
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves: Vec<Move> = self.geometry.all
+            .difference(&self.occupied())
+            .iter()
+            .map(|cell| {
+                let (q, r) = self.geometry.coords[cell];
+                Move::Place(q, r)
+            })
+            .collect();
+
+        if self.can_swap() {
+            moves.push(Move::Swap);
+        }
 
         moves
     }
 
-    // TODO: clean up duplicates
+    /// Swap is only on offer as the second player's reply to the very first stone.
+    pub fn can_swap(&self) -> bool {
+        self.rules.swap && self.turn == Player::P2 && self.occupied().count() == 1
+    }
+
+    // the winner is kept up to date by `place_stone`, so all of these are constant-time
+    pub fn result(&self) -> GameResult {
+        match self.win {
+            Some(win) => GameResult::Win(win.player),
+            None if self.is_full() => GameResult::Draw,
+            None => GameResult::Ongoing,
+        }
+    }
+
     pub fn is_terminal(&self) -> bool {
+        self.result() != GameResult::Ongoing
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.occupied() == self.geometry.all
+    }
+
+    /// The winning structure and its stones, if the game is over.
+    pub fn win_info(&self) -> Option<WinInfo> {
+        let win = self.win?;
         let detector = WinDetector::from_board(self);
-        detector.run(&Player::P1) || detector.run(&Player::P2)
+
+        match win.kind {
+            WinKind::Ring => detector.ring_through(win.cell).map(|ring| detector.ring_info(win.player, &ring)),
+            kind => Some(detector.connection_info(win.player, win.cell, kind)),
+        }
     }
 
-    pub fn get_winner(&self) -> Option<Player> {
-        let detector = win_detector::WinDetector::from_board(self);
-        if detector.run(&Player::P1) {
-            Some(Player::P1)
-        } else if detector.run(&Player::P2) {
-            Some(Player::P2)
-        } else {
-            None
+    /// Prints how the game ended, returning false while it is still going.
+    fn announce_result(&self) -> bool {
+        match self.result() {
+            GameResult::Win(_) => {
+                if let Some(win) = self.win_info() {
+                    println!("{}", win);
+                }
+                true
+            },
+            GameResult::Draw => {
+                println!("Draw: the board is full");
+                true
+            },
+            GameResult::Ongoing => false,
         }
     }
 
-    pub fn start_game_ai_vs_ai(&mut self, iters: usize, threads: Option<usize>) {
+    /// Plays `ais[0]` as the first player against `ais[1]`, so differently configured engines
+    /// can be compared. Each side gets its own copy of `time_control`. The game is saved to
+    /// `record_path` after every move.
+    pub fn start_game_ai_vs_ai(&mut self, mut ais: [mcts::MCTS; 2], time_control: TimeControl, record_path: Option<&Path>) -> GameResult {
+        // both engines keep their trees for the whole game and follow every move played
+        let mut time_controls = [time_control; 2];
+        let mut record = GameRecord::new(self, [PlayerInfo::engine("AI 1", ais[0].config()), PlayerInfo::engine("AI 2", ais[1].config())]);
 
         loop {
 
             self.print_state_pretty();
-            
-            let mut ai1 = mcts::MCTS::new();
+
+            let side = self.turn.index();
+            let budget = time_controls[side].budget(self);
+            ais[side].set_budget(budget);
 
             let start = Instant::now();
 
-            let best_move1;
+            let report = ais[side].run(self.clone());
+            let best_move = report.best_move;
 
-            match threads {
-                None => best_move1 = ai1.run(self.clone()),
-                Some(threads) => best_move1 = ai1.run_parallel(self.clone(), threads, iters)
-            }
-    
             let duration = start.elapsed();
+            time_controls[side].spend(duration);
 
-            println!("AI 1 plays: ({}, {}) in {:.2?}", best_move1.0, best_move1.1, duration);
-
-            self.apply_move(best_move1).unwrap();
-
-            if self.is_terminal() {
-                println!("AI 1 won");
-                break;
+            match time_controls[side] {
+                TimeControl::Clock(clock) => println!("AI {} plays: {} in {:.2?} of {}, {}", side + 1, self.format_move(best_move), duration, budget, clock),
+                TimeControl::PerMove(_) => println!("AI {} plays: {} in {:.2?} of {}", side + 1, self.format_move(best_move), duration, budget),
             }
 
-            let mut ai2 = mcts::MCTS::new();
-
-            let best_move2;
-    
-            match threads {
-                None => best_move2 = ai2.run(self.clone()),
-                Some(threads) => best_move2 = ai2.run_parallel(self.clone(), threads, iters)
+            self.apply_move(best_move).unwrap();
+            for ai in &mut ais {
+                ai.advance(best_move);
             }
 
-            println!("AI 2 plays: ({}, {}) in {:.2?}", best_move2.0, best_move2.1, duration);
-
-            self.apply_move(best_move2).unwrap();
+            record.push(best_move, duration, Some(MoveStats::from(&report)));
+            save_record(&record, record_path);
 
-            if self.is_terminal() {
-                println!("AI 2 won");
+            if self.announce_result() {
                 break;
             }
-            
-            
         }
 
         self.print_state_pretty();
 
+        self.result()
     }
 
-    pub fn start_game_vs_ai(&mut self) {
+    /// Plays `ai` on the side `ai_side` against a person at the terminal, the engine thinking
+    /// within `time_control`. The game is saved to `record_path` after every move.
+    pub fn start_game_vs_ai(&mut self, mut ai: mcts::MCTS, ai_side: Player, mut time_control: TimeControl, record_path: Option<&Path>) {
+        let mut players = [PlayerInfo::human("Human"), PlayerInfo::human("Human")];
+        players[ai_side.index()] = PlayerInfo::engine("AI", ai.config());
+        let mut record = GameRecord::new(self, players);
+
+        // undo takes back the AI's reply as well, so the person needs a move of their own
+        // and the reply to it on the board
+        let first_undoable_ply = if ai_side == Player::P1 { 3 } else { 2 };
+
         loop {
             // commented for debugs
             // self.clear_screen();
@@ -171,109 +419,266 @@ impl BoardState {
 
             self.print_state_pretty();
 
-            // TODO: move it elsewhere
-            let win_detector = WinDetector::from_board(self);
+            if self.announce_result() {
+                return;
+            }
 
-            println!("Enter move with format: q r");
+            if self.turn == ai_side {
+                print!("AI is thinking...");
 
-            let mut input: String = String::new();
+                ai.set_budget(time_control.budget(self));
 
-            stdin().read_line(&mut input).unwrap();
+                let start = Instant::now();
 
-            if input == "x" { break };
+                let report = ai.run(self.clone());
+                let best_move = report.best_move;
 
-            let chars: Vec<&str> = input.split_whitespace().collect();
+                let duration = start.elapsed();
+                time_control.spend(duration);
 
-            let q: i32 = chars[0].trim().parse().unwrap();
-            let r: i32 = chars[1].trim().parse().unwrap();
+                println!("AI plays: {} in {:.2?}", self.format_move(best_move), duration);
 
-            self.apply_move((q, r)).unwrap();
+                self.apply_move(best_move).unwrap();
+                ai.advance(best_move);
 
-            if self.is_terminal() {
-                println!("player {:?} won", self.turn);
-                return;
+                record.push(best_move, duration, Some(MoveStats::from(&report)));
+                save_record(&record, record_path);
+                continue;
             }
 
-            print!("AI is thinking...");
+            if self.can_swap() {
+                println!("Enter a move such as e5 or q r, swap, or undo to take back your last move");
+            } else {
+                println!("Enter a move such as e5 or q r, or undo to take back your last move");
+            }
 
-            let mut ai = mcts::MCTS::new();
+            let thinking = Instant::now();
+            let mut input: String = String::new();
 
-            let best_move = ai.run(self.clone());
-    
-            println!("AI plays: ({}, {})", best_move.0, best_move.1);
+            // the end of input, or an unreadable terminal, leaves the game as x does
+            if stdin().read_line(&mut input).unwrap_or(0) == 0 { return };
 
-            self.apply_move(best_move).unwrap();
+            if input.trim() == "x" { break };
 
-            if self.is_terminal() {
-                println!("player {:?} won", self.turn);
-                return;
+            if input.trim() == "undo" {
+                if self.ply() < first_undoable_ply {
+                    println!("nothing to take back");
+                } else {
+                    self.undo_to(self.ply() - 2);
+                    record.moves.truncate(self.ply());
+                    save_record(&record, record_path);
+                }
+                continue;
+            }
+
+            match self.parse_move(&input).and_then(|m| self.apply_move(m)) {
+                Ok(m) => {
+                    ai.advance(m);
+                    record.push(m, thinking.elapsed(), None);
+                    save_record(&record, record_path);
+                },
+                Err(e) => println!("{}", e),
             }
-            
         }
     }
 
-    // WARNING: draws are not handled
-    // pub fn evaluate(&self) -> f32 {
-    //     let result = self.is_terminal();
-
-    //     if !result {
-    //         panic!("Evaluation is not possible if the board is not terminal.");
-    //     }
+    pub fn apply_move(&mut self, m: Move) -> Result<Move, &'static str> {
+        let (q, r) = match m {
+            Move::Place(q, r) => (q, r),
+            Move::Swap => {
+                if !self.can_swap() {
+                    return Err("swap is not allowed now");
+                }
 
-    //     if self.turn == Player::P1 {
-    //         return 1.0;
-    //     };
+                let mut played = self.played(m, 0);
+                played.cell = self.swap_first_stone();
+                self.history.push(played);
+                return Ok(m);
+            },
+        };
 
-    //     if self.turn == Player::P2 {
-    //         return -1.0;
-    //     };
+        if !self.is_hex_in_bounds(q, r) {
+            return Err("move is out of bounds");
+        }
 
-    //     return 0.0;
-    // }
+        let cell = self.geometry.cell(q, r).unwrap();
 
-    pub fn apply_move(&mut self, (q, r): (i32, i32)) -> Result<(i32, i32), &'static str> {
-        if !self.is_hex_in_bounds(q, r) {
-        return Err("move is out of bounds");
+        if self.owner(cell) != HexOwner::None {
+            return Err("cell already occupied");
         }
 
-        match self.state.get(&(q, r)) {
-            Some(hex) => {
-                if hex.owner != HexOwner::None {
-                    return Err("cell already occupied");
-                }
+        let played = self.played(m, cell);
+        self.place_stone(cell);
+        self.history.push(played);
+
+        Ok(m)
+    }
+
+    fn played(&self, m: Move, cell: usize) -> Played {
+        Played { m, cell, win: self.win, threats: self.threats, groups_mark: self.groups.mark() }
+    }
+
+    /// Takes back the last move, restoring the side to move, the win state and the groups.
+    pub fn undo_move(&mut self) -> Result<Move, &'static str> {
+        let played = self.history.pop().ok_or("no moves to undo")?;
+
+        self.next_turn();
+
+        match played.m {
+            Move::Place(..) => self.toggle_stone(self.turn, played.cell),
+            Move::Swap => {
+                self.toggle_stone(Player::P2, played.cell);
+                self.toggle_stone(Player::P1, played.cell);
             },
-            None => return Err("invalid cell"),
         }
 
-        let hex_owner: HexOwner = HexOwner::from(&self.turn);
+        self.groups.rollback(played.groups_mark);
+        self.win = played.win;
+        self.threats = played.threats;
+
+        Ok(played.m)
+    }
 
-        self.state.insert((q, r), Hex { q, r, owner: hex_owner});
+    /// Cell of the stone played `back` moves before the last one; for a swap, the stone that
+    /// changed hands.
+    pub fn recent_cell(&self, back: usize) -> Option<usize> {
+        self.history.iter().rev().nth(back).map(|played| played.cell)
+    }
+
+    /// Every empty cell where a stone of `player` would win at once, by bridge, fork or ring.
+    /// Kept up to date by every move, so asking costs nothing.
+    pub fn winning_cells(&self, player: Player) -> Bitboard {
+        self.threats[player.index()]
+    }
+
+    /// Whether a stone of `player` on the empty `cell` would win. Bridges and forks come from
+    /// the corner and side masks of the groups the stone would join, so only a cell that
+    /// closes a loop costs a flood fill.
+    fn wins_at(&self, player: Player, cell: usize) -> bool {
+        let own = self.stones[player.index()];
+        let roots = self.groups.neighbour_roots(self.geometry, cell, |n| own.contains(n));
+        let mut corners = self.geometry.corner_bits[cell];
+        let mut sides = self.geometry.side_bits[cell];
+
+        for root in roots.into_iter().flatten() {
+            corners |= self.groups.corners(root);
+            sides |= self.groups.sides(root);
+        }
+
+        corners.count_ones() >= 2
+            || sides.count_ones() >= 3
+            || (Groups::loop_among(&roots) && WinDetector::from_board(self).ring_with(player, cell))
+    }
+
+    /// Brings the winning cells up to date after `player` put a stone on `cell`. For `player`
+    /// only the empty cells next to the group the stone joined can have changed, as every other
+    /// cell borders the same groups as before; the opponent just loses `cell`.
+    fn update_threats(&mut self, player: Player, cell: usize) {
+        for threats in &mut self.threats {
+            threats.remove(cell);
+        }
+
+        let root = self.groups.find(cell);
+        let mut threats = self.threats[player.index()];
+
+        // another stone only adds to what a cell would complete, so a winning cell stays one
+        for n in self.groups.adjacent(root).difference(&self.occupied().union(&threats)).iter() {
+            if self.wins_at(player, n) {
+                threats.insert(n);
+            }
+        }
+
+        self.threats[player.index()] = threats;
+    }
+
+    /// Moves played so far, in order.
+    pub fn moves(&self) -> impl Iterator<Item = Move> + '_ {
+        self.history.iter().map(|played| played.m)
+    }
+
+    /// Number of moves played so far, swap included.
+    pub fn ply(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Takes moves back until only `ply` of them are left.
+    pub fn undo_to(&mut self, ply: usize) {
+        while self.history.len() > ply {
+            self.undo_move().unwrap();
+        }
+    }
+
+    /// Hands the first player's only stone to the second player, who then waits for
+    /// the first player's next move as if they had placed it.
+    fn swap_first_stone(&mut self) -> usize {
+        let cell = self.stones[Player::P1.index()].iter().next().expect("swap needs a stone on the board");
+
+        self.toggle_stone(Player::P1, cell);
+        self.toggle_stone(Player::P2, cell);
+        self.groups.add(self.geometry, cell);
+
+        // the stone changed hands, so both players' winning cells change: one stone is cheap to redo
+        self.threats = [Bitboard::default(); 2];
+        self.update_threats(Player::P2, cell);
 
         self.next_turn();
 
-        Ok((q, r))
+        cell
     }
 
-    // --- DEBUG AND GAME LOGIC, TO BE MOVED LATER ---
+    fn place_stone(&mut self, cell: usize) {
+        let player = self.turn;
+        let own = self.stones[player.index()];
+
+        // has to be asked before the new stone joins its neighbours
+        let closes_loop = self.groups.closes_loop(self.geometry, cell, |n| own.contains(n));
+
+        self.toggle_stone(player, cell);
+        self.groups.add(self.geometry, cell);
+
+        for n in self.geometry.neighbours_of(cell) {
+            if own.contains(n) {
+                self.groups.union(cell, n);
+            }
+        }
 
-    pub fn make_move(&mut self, q: i32, r: i32) {
-        let hex_owner: HexOwner = HexOwner::from(&self.turn);
+        if self.win.is_none() {
+            let root = self.groups.find(cell);
+
+            // a ring can only appear when the new stone closes a loop
+            let kind = if self.groups.corner_count(root) >= 2 {
+                Some(WinKind::Bridge)
+            } else if self.groups.side_count(root) >= 3 {
+                Some(WinKind::Fork)
+            } else if closes_loop && WinDetector::from_board(self).ring_through(cell).is_some() {
+                Some(WinKind::Ring)
+            } else {
+                None
+            };
+
+            self.win = kind.map(|kind| Win { player, kind, cell });
+        }
 
-        self.state.insert((q, r), Hex { q, r, owner: hex_owner});
+        self.update_threats(player, cell);
 
         self.next_turn();
     }
 
+    // --- DEBUG AND GAME LOGIC, TO BE MOVED LATER ---
+
     fn next_turn(&mut self) {
-        self.turn = match self.turn {
-            Player::P1 => Player::P2,
-            Player::P2 => Player::P1,
-        };
+        self.turn = self.turn.opponent();
+        self.hash ^= ZOBRIST_P2_TO_MOVE;
     }
 
+    #[allow(dead_code)]
     fn clear_screen(&self) { print!("\x1B[2J\x1B[1;1H"); }
 
-    pub fn start_game(&mut self) {
+    /// Two people taking turns at the same terminal. The game is saved to `record_path` after
+    /// every move.
+    pub fn start_game(&mut self, record_path: Option<&Path>) {
+        let mut record = GameRecord::new(self, [PlayerInfo::human("Player 1"), PlayerInfo::human("Player 2")]);
+
         loop {
             // commented for debugs
             // self.clear_screen();
@@ -282,36 +687,62 @@ impl BoardState {
 
             self.print_state_pretty();
 
-            // TODO: move it elsewhere
-            let win_detector = WinDetector::from_board(self);
-
-            if win_detector.run(&self.turn) {
-                println!("player {:?} won", self.turn);
+            if self.announce_result() {
                 return;
             }
 
-            println!("Enter move with format: q r");
+            if self.can_swap() {
+                println!("Enter a move such as e5 or q r, swap, or undo");
+            } else {
+                println!("Enter a move such as e5 or q r, or undo");
+            }
 
+            let thinking = Instant::now();
             let mut input: String = String::new();
 
-            stdin().read_line(&mut input).unwrap();
+            // the end of input, or an unreadable terminal, leaves the game as x does
+            if stdin().read_line(&mut input).unwrap_or(0) == 0 { return };
 
-            if input == "x" { break };
+            if input.trim() == "x" { break };
 
-            let chars: Vec<&str> = input.split_whitespace().collect();
-
-            let q: i32 = chars[0].trim().parse().unwrap();
-            let r: i32 = chars[1].trim().parse().unwrap();
-
-            if !self.is_hex_in_bounds(q, r) { continue; } 
+            if input.trim() == "undo" {
+                match self.undo_move() {
+                    Ok(_) => {
+                        record.moves.truncate(self.ply());
+                        save_record(&record, record_path);
+                    },
+                    Err(e) => println!("{}", e),
+                }
+                continue;
+            }
 
-            self.make_move(q, r);
+            match self.parse_move(&input).and_then(|m| self.apply_move(m)) {
+                Ok(m) => {
+                    record.push(m, thinking.elapsed(), None);
+                    save_record(&record, record_path);
+                },
+                Err(e) => println!("{}", e),
+            }
 
             
     }
     }
 
     pub fn print_state_pretty(&self) {
+        // the winning structure is highlighted when printing to a terminal,
+        // logs redirected to a file stay plain
+        let highlighted: Vec<(i32, i32)> = match self.win_info() {
+            Some(win) if std::io::stdout().is_terminal() => win.cells,
+            _ => vec![],
+        };
+
+        let mut rows = String::new();
+        self.write_rows(&mut rows, &highlighted).unwrap();
+        print!("{}", rows);
+    }
+
+    /// The board as rows of `X`, `O` and `.`, with the cells in `highlighted` in bold red.
+    fn write_rows(&self, f: &mut impl fmt::Write, highlighted: &[(i32, i32)]) -> fmt::Result {
         let n = (self.board_size - 1) as i32; 
         
         for r in -n..=n {
@@ -321,28 +752,167 @@ impl BoardState {
             
             let max_length = (2 * self.board_size - 1) as usize;
             let indent_count = max_length - row_length;
-            print!("{}", " ".repeat(indent_count));
+            write!(f, "{}", " ".repeat(indent_count))?;
             
             for q in q_min..=q_max {
-                let key = (q as i32, r as i32);
-                let symbol = match self.state.get(&key) {
-                    Some(hex) => match hex.owner {
-                        HexOwner::None => '.',
-                        HexOwner::P1 => 'X',
-                        HexOwner::P2 => 'O',
-                    },
-                    None => '.',
+                let symbol = match self.owner_at(q, r) {
+                    HexOwner::None => '.',
+                    HexOwner::P1 => 'X',
+                    HexOwner::P2 => 'O',
                 };
-                print!("{} ", symbol);
+                if highlighted.contains(&(q, r)) {
+                    write!(f, "\x1B[1;31m{}\x1B[0m ", symbol)?;
+                } else {
+                    write!(f, "{} ", symbol)?;
+                }
             }
-            println!();
+            writeln!(f)?;
         }
+
+        Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn print_state_less_pretty(&self) {
-        for (q, r) in self.state.keys() {
+        for (q, r) in &self.geometry.coords {
             println!("{} {}", q, r);
         }
     }
 
 }
+
+/// Rewrites the record after every move, so an interrupted game is kept as well.
+fn save_record(record: &GameRecord, path: Option<&Path>) {
+    if let Some(path) = path && let Err(e) = record.save(path) {
+        eprintln!("could not save the game: {}", e);
+    }
+}
+
+/// The plain diagram `print_state_pretty` draws, without any highlighting.
+impl fmt::Display for BoardState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_rows(f, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::IndexedRandom, SeedableRng};
+
+    use super::{BoardState, GameResult, Move, Notation, Player, Rules};
+    use crate::bitboard::{Bitboard, Geometry, MAX_BOARD_SIZE};
+
+    /// Everything a move changes: hash, side to move, result, stones, winning cells, and the
+    /// root, corners, sides and adjacent cells of the group of every cell.
+    type Snapshot = (u64, Player, GameResult, [Bitboard; 2], [Bitboard; 2], Vec<(usize, u8, u8, Bitboard)>);
+
+    fn snapshot(board: &BoardState) -> Snapshot {
+        let groups = (0..board.geometry.coords.len()).map(|cell| {
+            let root = board.groups.find(cell);
+            (root, board.groups.corners(root), board.groups.sides(root), *board.groups.adjacent(root))
+        }).collect();
+
+        let threats = [Player::P1, Player::P2].map(|p| board.winning_cells(p));
+        (board.hash(), board.turn, board.result(), board.stones, threats, groups)
+    }
+
+    #[test]
+    fn notation_round_trips_on_every_cell() {
+        for size in 2..=MAX_BOARD_SIZE {
+            for &(q, r) in &Geometry::for_size(size).coords {
+                let m = Move::Place(q, r);
+                let written = m.to_notation(size);
+
+                assert_eq!(Move::from_notation(&written, size), Ok(m), "{} on size {}", written, size);
+            }
+        }
+    }
+
+    #[test]
+    fn notation_matches_little_golem() {
+        // a1 is the bottom-left corner, j10 the centre and s10 the right corner of size 10
+        assert_eq!(Move::from_notation("a1", 10), Ok(Move::Place(-9, 9)));
+        assert_eq!(Move::from_notation("j10", 10), Ok(Move::Place(0, 0)));
+        assert_eq!(Move::from_notation("S10", 10), Ok(Move::Place(9, 0)));
+        assert_eq!(Move::from_notation("e5", 5), Ok(Move::Place(0, 0)));
+        assert_eq!(Move::from_notation("swap", 5), Ok(Move::Swap));
+        assert_eq!(Move::Place(4, -4).to_notation(5), "i9");
+        assert_eq!(Move::Place(4, 0).to_notation(5), "i5");
+    }
+
+    #[test]
+    fn notation_rejects_cells_off_the_board() {
+        for input in ["a11", "t1", "a0", "s1", "j20", "e", "5", "", "a-2147483648", "e+5", "e-1", "e005", "e 5", "e5x"] {
+            assert!(Move::from_notation(input, 10).is_err(), "{} was accepted", input);
+        }
+    }
+
+    #[test]
+    fn board_reads_both_notations_and_prints_its_own() {
+        let mut board = BoardState::new(5);
+
+        assert_eq!(board.parse_move("c4"), board.parse_move("(-2, 1)"));
+        assert_eq!(board.parse_move("1 -1"), Ok(Move::Place(1, -1)));
+        assert_eq!(board.format_move(Move::Place(-2, 1)), "c4");
+
+        board.notation = Notation::Axial;
+        assert_eq!(board.format_move(Move::Place(-2, 1)), "(-2, 1)");
+    }
+
+    #[test]
+    fn winning_cells_are_the_moves_that_win() {
+        let mut rng = StdRng::seed_from_u64(5);
+
+        for board_size in [3, 4, 5, 6] {
+            for _ in 0..20 {
+                let mut board = BoardState::new(board_size);
+
+                while !board.is_terminal() {
+                    for player in [Player::P1, Player::P2] {
+                        let cells = board.winning_cells(player);
+
+                        for cell in board.geometry.all.difference(&board.occupied()).iter() {
+                            let (q, r) = board.geometry.coords[cell];
+                            let mut after = board.clone();
+                            after.turn = player;
+                            after.apply_move(Move::Place(q, r)).unwrap();
+
+                            assert_eq!(
+                                cells.contains(cell),
+                                after.result() == GameResult::Win(player),
+                                "{:?} at ({}, {}) after {:?}", player, q, r, board.moves().collect::<Vec<_>>(),
+                            );
+                        }
+                    }
+
+                    board.apply_move(*board.legal_moves().choose(&mut rng).unwrap()).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn undo_restores_every_position_of_a_game() {
+        let mut rng = StdRng::seed_from_u64(3);
+
+        for board_size in [3, 4, 6] {
+            for _ in 0..5 {
+                let mut board = BoardState::with_rules(board_size, Rules { swap: true });
+                let mut before = vec![];
+
+                while !board.is_terminal() {
+                    // the swap is always taken, so that it is undone as well
+                    let m = if board.can_swap() { Move::Swap } else { *board.legal_moves().choose(&mut rng).unwrap() };
+
+                    before.push(snapshot(&board));
+                    board.apply_move(m).unwrap();
+                }
+
+                while let Some(expected) = before.pop() {
+                    board.undo_move().unwrap();
+                    assert_eq!(snapshot(&board), expected, "after undoing back to ply {}", before.len());
+                }
+            }
+        }
+    }
+}
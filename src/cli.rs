@@ -0,0 +1,554 @@
+use std::{fs, io, path::{Path, PathBuf}, str::FromStr, time::Duration};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    bitboard::MAX_BOARD_SIZE,
+    board::{BoardState, GameResult, Notation, Player, Rules},
+    clock::{Clock, TimeControl},
+    config::MctsConfig,
+    gtp,
+    import::import_log,
+    mcts::{BetaSchedule, MoveSelection, ParallelMode, MCTS},
+    playout::Playout,
+    record::GameRecord,
+};
+
+pub const USAGE: &str = "\
+usage: monte_hav <command> [options]
+
+commands:
+  play                 a game at the terminal, between two people or against the engine
+  selfplay             the engine against itself, or a match between two configs
+  analyze <record>     the engine's view of every move of a game record or games/ log,
+                       or of one position with --ply
+  engine               the GTP-style text protocol on stdin and stdout
+  bench                one search from the empty board, with its speed; with several
+                       threads one for each of root and tree parallelism
+  solve [record]       prove the empty board or a recorded position won or lost
+
+options:
+  --size <n>           board size, 2 to 10 (default 5)
+  --swap               let the second player take the first stone over
+  --iterations <n>     iterations per search
+  --time <seconds>     time per search; with --iterations whichever runs out first
+  --clock <seconds>    a game clock per side instead of a budget per move (play, selfplay)
+  --increment <secs>   time added to the clock after every move
+  --threads <n>        search threads
+  --parallel <mode>    root or tree: how more than one thread shares a search
+  --seed <n>           seed for reproducible searches
+  --exploration <c>    UCT exploration constant
+  --max-nodes <n>      stop a search once the tree holds this many nodes
+  --selection <rule>   most_visits, highest_mean or secure: which root move is played
+  --transpositions     share statistics between transpositions
+  --rave <k|off>       RAVE equivalence parameter, or off for plain UCT
+  --widening <c,e>     progressive widening to c * visits^e children
+  --playout <policy>   uniform or heavy playouts
+  --quiet              no search statistics after every move
+  --config <file>      engine settings from a .toml or .json file, before the options above
+  --config2 <file>     settings of the second engine in selfplay, the options above applying
+                       to both; the same as the first by default
+  --games <n>          selfplay games, the engines taking turns to move first (default 1)
+  --output <file>      save the game record to this file (play, selfplay, analyze), numbered
+                       file-1, file-2, ... for several games
+  --ai <first|second>  the side the engine takes in play, none by default
+  --ply <n>            position after this many moves (analyze, solve)
+  --game <n>           game of a log holding several (analyze, solve; default 1)
+  --axial              print moves as (q, r) instead of e5";
+
+/// Board size when `--size` is not given.
+const DEFAULT_BOARD_SIZE: i8 = 5;
+
+#[derive(Debug)]
+enum Command {
+    Play,
+    SelfPlay,
+    Analyze(PathBuf),
+    Engine,
+    Bench,
+    Solve(Option<PathBuf>),
+    Help,
+}
+
+/// A command with everything its options set.
+#[derive(Debug)]
+pub struct Cli {
+    command: Command,
+    board_size: i8,
+    rules: Rules,
+    notation: Notation,
+    config: MctsConfig,
+    /// the second engine's settings in selfplay, when they differ from the first
+    config2: Option<MctsConfig>,
+    games: usize,
+    output: Option<PathBuf>,
+    ai_side: Option<Player>,
+    /// total time on each side's clock and its increment, for the game loops
+    clock: Option<(Duration, Duration)>,
+    ply: Option<usize>,
+    game: usize,
+}
+
+impl Cli {
+    /// Reads the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let name = args.next().ok_or("expected a command")?;
+
+        let mut cli = Self {
+            command: Command::Help,
+            board_size: DEFAULT_BOARD_SIZE,
+            rules: Rules::default(),
+            notation: Notation::default(),
+            config: MctsConfig::default(),
+            config2: None,
+            games: 1,
+            output: None,
+            ai_side: None,
+            clock: None,
+            ply: None,
+            game: 1,
+        };
+        let mut record = None;
+        let mut tuning = Tuning::default();
+        let mut increment = None;
+        let mut config2 = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--size" => cli.board_size = number(&arg, &value()?)?,
+                "--swap" => cli.rules.swap = true,
+                "--iterations" => tuning.iterations = Some(number(&arg, &value()?)?),
+                "--time" => tuning.time = Some(seconds(&arg, &value()?)?),
+                "--threads" => tuning.threads = Some(number(&arg, &value()?)?),
+                "--parallel" => tuning.parallel_mode = Some(named(&arg, &value()?)?),
+                "--seed" => tuning.seed = Some(number(&arg, &value()?)?),
+                "--exploration" => tuning.exploration_constant = Some(number(&arg, &value()?)?),
+                "--max-nodes" => tuning.max_nodes = Some(number(&arg, &value()?)?),
+                "--selection" => tuning.move_selection = Some(named(&arg, &value()?)?),
+                "--transpositions" => tuning.transpositions = true,
+                "--rave" => tuning.rave = Some(match value()?.as_str() {
+                    "off" => None,
+                    k => Some(BetaSchedule::Equivalence(number(&arg, k)?)),
+                }),
+                "--widening" => {
+                    let value = value()?;
+                    let (coefficient, exponent) = value.split_once(',').ok_or(format!("--widening takes c,e, not `{}`", value))?;
+                    tuning.widening = Some((number(&arg, coefficient)?, number(&arg, exponent)?));
+                },
+                "--playout" => tuning.playout = Some(named(&arg, &value()?)?),
+                "--quiet" => tuning.quiet = true,
+                "--clock" => cli.clock = Some((seconds(&arg, &value()?)?, Duration::ZERO)),
+                "--increment" => increment = Some(seconds(&arg, &value()?)?),
+                "--config" => cli.config = MctsConfig::load(value()?)?,
+                "--config2" => config2 = Some(MctsConfig::load(value()?)?),
+                "--games" => cli.games = number(&arg, &value()?)?,
+                "--output" => cli.output = Some(PathBuf::from(value()?)),
+                "--ai" => cli.ai_side = match value()?.as_str() {
+                    "first" => Some(Player::P1),
+                    "second" => Some(Player::P2),
+                    side => return Err(format!("--ai takes first or second, not `{}`", side)),
+                },
+                "--ply" => cli.ply = Some(number(&arg, &value()?)?),
+                "--game" => cli.game = number(&arg, &value()?)?,
+                "--axial" => cli.notation = Notation::Axial,
+                option if option.starts_with("--") => return Err(format!("unknown option `{}`", option)),
+                path if record.is_none() => record = Some(PathBuf::from(path)),
+                extra => return Err(format!("unexpected argument `{}`", extra)),
+            }
+        }
+
+        if !(2..=MAX_BOARD_SIZE).contains(&cli.board_size) {
+            return Err(format!("--size must be between 2 and {}", MAX_BOARD_SIZE));
+        }
+        if let Some(increment) = increment {
+            let (_, clock_increment) = cli.clock.as_mut().ok_or("--increment needs --clock")?;
+            *clock_increment = increment;
+        }
+        if cli.game == 0 {
+            return Err("--game counts from 1".to_string());
+        }
+        if cli.games == 0 {
+            return Err("--games needs at least one game".to_string());
+        }
+
+        cli.config = tuning.apply(cli.config);
+        cli.config2 = config2.map(|config| tuning.apply(config));
+
+        cli.command = match (name.as_str(), record) {
+            ("analyze", Some(record)) => Command::Analyze(record),
+            ("analyze", None) => return Err("analyze needs a record".to_string()),
+            ("solve", record) => Command::Solve(record),
+            (_, Some(record)) => return Err(format!("unexpected argument `{}`", record.display())),
+            ("play", None) => Command::Play,
+            ("selfplay", None) => Command::SelfPlay,
+            ("engine", None) => Command::Engine,
+            ("bench", None) => Command::Bench,
+            ("help" | "--help" | "-h", None) => Command::Help,
+            (command, None) => return Err(format!("unknown command `{}`", command)),
+        };
+
+        if !matches!(cli.command, Command::SelfPlay) && (cli.config2.is_some() || cli.games > 1) {
+            return Err("--config2 and --games are for selfplay".to_string());
+        }
+
+        Ok(cli)
+    }
+
+    pub fn run(self) -> Result<(), String> {
+        match &self.command {
+            Command::Play => {
+                let mut board = self.board();
+
+                match self.ai_side {
+                    Some(side) => {
+                        let ai = MCTS::with_config(self.config.clone());
+                        board.start_game_vs_ai(ai, side, self.time_control(), self.output.as_deref());
+                    },
+                    None => board.start_game(self.output.as_deref()),
+                }
+            },
+            Command::SelfPlay => {
+                self.self_play();
+            },
+            Command::Analyze(path) => self.analyze(path)?,
+            Command::Engine => {
+                gtp::Engine::new(self.board(), self.config.clone())
+                    .run(io::stdin().lock(), io::stdout())
+                    .map_err(|e| e.to_string())?;
+            },
+            Command::Bench => {
+                let board = self.board();
+                let threads = self.config.threads.max(1);
+
+                // one thread searches the same way in either mode
+                let modes: &[ParallelMode] = match threads {
+                    1 => &[self.config.parallel_mode],
+                    _ => &[ParallelMode::Root, ParallelMode::Tree],
+                };
+
+                for &mode in modes {
+                    println!("size {}, {} threads, {:?} parallelism, {}", board.board_size, threads, mode, self.config.budget());
+
+                    let config = self.config.clone().threads(threads, mode).verbose(false);
+                    let report = MCTS::with_config(config).analyze(board.clone());
+                    print!("{}", report);
+                    println!("{:.0} nodes/s", report.nodes_per_second());
+                }
+            },
+            Command::Solve(path) => {
+                let board = match path {
+                    Some(path) => self.load(path)?.board_at(self.ply.unwrap_or(usize::MAX)),
+                    None => self.board(),
+                };
+                let board = self.with_notation(board);
+
+                if board.is_terminal() {
+                    return Err("the game is already over".to_string());
+                }
+
+                let mut mcts = MCTS::with_config(self.config.clone().verbose(false));
+                let report = mcts.analyze(board.clone());
+                print!("{}", report);
+
+                if report.solved.is_none() {
+                    println!("not solved within {}", self.config.budget());
+                }
+            },
+            Command::Help => println!("{}", USAGE),
+        }
+
+        Ok(())
+    }
+
+    /// Plays `--games` games between the engine and the `--config2` one, or itself, and
+    /// prints the score. Returns the wins of each engine and the draws.
+    fn self_play(&self) -> ([usize; 2], usize) {
+        let configs = [&self.config, self.config2.as_ref().unwrap_or(&self.config)];
+        let mut wins = [0; 2];
+        let mut draws = 0;
+
+        for game in 0..self.games {
+            // the engines take turns to move first
+            let order = if game % 2 == 0 { [0, 1] } else { [1, 0] };
+
+            // the same settings on both sides, but not the same random choices, nor those of another game
+            let ais = order.map(|engine| MCTS::with_config(MctsConfig {
+                seed: configs[engine].seed.map(|seed| seed.wrapping_add(2 * game as u64 + engine as u64)),
+                ..configs[engine].clone()
+            }));
+
+            let output = self.output.as_deref().map(|path| match self.games {
+                1 => path.to_path_buf(),
+                _ => numbered(path, game + 1),
+            });
+
+            let result = self.board().start_game_ai_vs_ai(ais, self.time_control(), output.as_deref());
+
+            match result {
+                GameResult::Win(player) => wins[order[player.index()]] += 1,
+                _ => draws += 1,
+            }
+
+            if self.games > 1 {
+                println!("game {}: engine 1 {}, engine 2 {}, {} drawn", game + 1, wins[0], wins[1], draws);
+            }
+        }
+
+        if self.games > 1 || self.config2.is_some() {
+            // a draw counts as half a win
+            let score = (wins[0] as f64 + draws as f64 / 2.0) / self.games as f64;
+            println!("engine 1 won {} and engine 2 won {} of {} games, {} drawn: engine 1 scored {:.1}%", wins[0], wins[1], self.games, draws, score * 100.0);
+        }
+
+        (wins, draws)
+    }
+
+    /// Searches every position of a game, or the one at `--ply`, and compares the engine's
+    /// choice with the move played.
+    fn analyze(&self, path: &Path) -> Result<(), String> {
+        let record = self.load(path)?;
+        let mut mcts = MCTS::with_config(self.config.clone().verbose(false));
+
+        if let Some(output) = &self.output {
+            record.save(output)?;
+        }
+
+        if let Some(ply) = self.ply {
+            let board = self.with_notation(record.board_at(ply));
+
+            if board.is_terminal() {
+                return Err("the game is already over".to_string());
+            }
+
+            print!("{}", mcts.analyze(board));
+            return Ok(());
+        }
+
+        let mut board = self.with_notation(record.board_at(0));
+
+        for (i, recorded) in record.moves.iter().enumerate() {
+            let report = mcts.analyze(board.clone());
+            let verdict = if report.best_move == recorded.m { "agrees" } else { "prefers" };
+
+            println!(
+                "{:>3}. {:?} played {:<6} engine {:<7} {:<6} win probability {:.1}%",
+                i + 1,
+                board.turn,
+                board.format_move(recorded.m),
+                verdict,
+                board.format_move(report.best_move),
+                report.win_probability * 100.0,
+            );
+
+            board.apply_move(recorded.m).unwrap();
+            mcts.advance(recorded.m);
+        }
+
+        if let Some(win) = board.win_info() {
+            println!("{}", win);
+        }
+
+        Ok(())
+    }
+
+    /// A game record, or the `--game`th game of a console log from `games/`.
+    fn load(&self, path: &Path) -> Result<GameRecord, String> {
+        if path.extension().is_some_and(|extension| extension == "txt") {
+            let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let mut games = import_log(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+            if self.game > games.len() {
+                return Err(format!("{}: has {} games", path.display(), games.len()));
+            }
+
+            return Ok(games.swap_remove(self.game - 1));
+        }
+
+        GameRecord::load(path)
+    }
+
+    /// The `--clock` with its increment, or the configured budget for every move.
+    fn time_control(&self) -> TimeControl {
+        match self.clock {
+            Some((total, increment)) => TimeControl::Clock(Clock::new(total, increment)),
+            None => TimeControl::PerMove(self.config.budget()),
+        }
+    }
+
+    fn board(&self) -> BoardState {
+        self.with_notation(BoardState::with_rules(self.board_size, self.rules))
+    }
+
+    fn with_notation(&self, mut board: BoardState) -> BoardState {
+        board.notation = self.notation;
+        board
+    }
+}
+
+/// Engine settings given as options, laid over the defaults or a config file.
+#[derive(Debug, Default)]
+struct Tuning {
+    iterations: Option<u32>,
+    time: Option<Duration>,
+    threads: Option<usize>,
+    parallel_mode: Option<ParallelMode>,
+    seed: Option<u64>,
+    exploration_constant: Option<f32>,
+    max_nodes: Option<usize>,
+    move_selection: Option<MoveSelection>,
+    transpositions: bool,
+    rave: Option<Option<BetaSchedule>>,
+    widening: Option<(f32, f32)>,
+    playout: Option<Playout>,
+    quiet: bool,
+}
+
+impl Tuning {
+    fn apply(&self, mut config: MctsConfig) -> MctsConfig {
+        // options given on the command line replace the budget of a config file as a whole
+        if self.iterations.is_some() || self.time.is_some() {
+            config.iterations = None;
+            config.time_ms = None;
+        }
+        if let Some(iterations) = self.iterations {
+            config = config.iterations(iterations);
+        }
+        if let Some(time) = self.time {
+            config = config.time(time);
+        }
+        if self.threads.is_some() || self.parallel_mode.is_some() {
+            let (threads, parallel_mode) = (self.threads.unwrap_or(config.threads), self.parallel_mode.unwrap_or(config.parallel_mode));
+            config = config.threads(threads, parallel_mode);
+        }
+        if let Some(seed) = self.seed {
+            config = config.seed(seed);
+        }
+        if let Some(c) = self.exploration_constant {
+            config = config.exploration_constant(c);
+        }
+        if let Some(max_nodes) = self.max_nodes {
+            config = config.max_nodes(max_nodes);
+        }
+        if let Some(move_selection) = self.move_selection {
+            config = config.move_selection(move_selection);
+        }
+        if self.transpositions {
+            config = config.transpositions(true);
+        }
+        match self.rave {
+            Some(Some(schedule)) => config = config.rave(schedule),
+            Some(None) => config.rave = None,
+            None => {},
+        }
+        if let Some((coefficient, exponent)) = self.widening {
+            config = config.widening(coefficient, exponent);
+        }
+        if let Some(playout) = self.playout {
+            config = config.playout(playout);
+        }
+        if self.quiet {
+            config = config.verbose(false);
+        }
+
+        config
+    }
+}
+
+/// `path` with `-n` added to its file name, before the extension.
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{}-{}.{}", stem, n, extension.to_string_lossy())),
+        None => path.with_file_name(format!("{}-{}", stem, n)),
+    }
+}
+
+fn number<T: FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} takes a number, not `{}`", option, value))
+}
+
+fn seconds(option: &str, value: &str) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(number(option, value)?).map_err(|_| format!("{} takes a number of seconds, not `{}`", option, value))
+}
+
+/// A setting spelt as in a config file, such as `tree` or `most_visits`.
+fn named<T: DeserializeOwned>(option: &str, value: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("{} does not take `{}`", option, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use super::{numbered, Cli, Command};
+    use crate::{clock::TimeControl, mcts::{BetaSchedule, MoveSelection, ParallelMode, ProgressiveWidening}, playout::Playout};
+
+    fn parse(line: &str) -> Result<Cli, String> {
+        Cli::parse(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn options_set_the_board_and_the_budget() {
+        let cli = parse("selfplay --size 7 --swap --time 2.5 --threads 4 --seed 9").unwrap();
+
+        assert!(matches!(cli.command, Command::SelfPlay));
+        assert_eq!((cli.board_size, cli.rules.swap), (7, true));
+        assert_eq!((cli.config.iterations, cli.config.time_ms), (None, Some(2500)));
+        assert_eq!((cli.config.threads, cli.config.seed), (4, Some(9)));
+    }
+
+    #[test]
+    fn a_clock_replaces_the_budget_per_move() {
+        let cli = parse("selfplay --clock 300 --increment 5").unwrap();
+        assert_eq!(cli.clock, Some((Duration::from_secs(300), Duration::from_secs(5))));
+        assert!(matches!(cli.time_control(), TimeControl::Clock(_)));
+
+        assert!(matches!(parse("selfplay").unwrap().time_control(), TimeControl::PerMove(_)));
+        assert!(parse("selfplay --increment 5").is_err());
+    }
+
+    #[test]
+    fn options_set_every_engine_setting() {
+        let cli = parse("bench --parallel tree --exploration 0.5 --max-nodes 9000 --selection secure --transpositions --rave off --widening 2,0.5 --playout heavy --quiet").unwrap();
+        let config = cli.config;
+
+        assert_eq!((config.threads, config.parallel_mode), (1, ParallelMode::Tree));
+        assert_eq!((config.exploration_constant, config.max_nodes), (0.5, Some(9000)));
+        assert_eq!((config.move_selection, config.transpositions, config.rave), (MoveSelection::Secure, true, None));
+        assert_eq!(config.widening, Some(ProgressiveWidening { coefficient: 2.0, exponent: 0.5 }));
+        assert_eq!((config.playout, config.verbose), (Playout::Heavy, false));
+
+        assert_eq!(parse("play --rave 300").unwrap().config.rave, Some(BetaSchedule::Equivalence(300.0)));
+        for line in ["bench --parallel leaf", "bench --widening 2", "bench --selection best", "bench --time -1"] {
+            assert!(parse(line).is_err(), "{} was accepted", line);
+        }
+    }
+
+    #[test]
+    fn selfplay_plays_a_match_with_the_sides_alternating() {
+        let cli = parse("selfplay --size 3 --iterations 20 --seed 1 --games 3 --quiet").unwrap();
+        let (wins, draws) = cli.self_play();
+
+        assert_eq!(wins[0] + wins[1] + draws, 3);
+        assert_eq!(numbered(Path::new("out/game.sgf"), 2), Path::new("out/game-2.sgf"));
+
+        for line in ["selfplay --games 0", "play --games 2", "bench --games 2", "selfplay --config2 missing.toml"] {
+            assert!(parse(line).is_err(), "{} was accepted", line);
+        }
+    }
+
+    #[test]
+    fn records_are_taken_only_where_they_are_read() {
+        assert!(matches!(parse("analyze game.sgf --ply 3").unwrap().command, Command::Analyze(_)));
+        assert!(matches!(parse("solve").unwrap().command, Command::Solve(None)));
+
+        for line in ["analyze", "play game.sgf", "bench --size 11", "engine --threads", "play --ai both", "fly"] {
+            assert!(parse(line).is_err(), "{} was accepted", line);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fmt, time::Duration};
+
+use crate::{board::BoardState, mcts::Budget};
+
+/// Never plan for fewer moves than this, so one long think early on cannot drain the clock.
+const MIN_MOVES_TO_GO: u32 = 10;
+
+/// How the engines in a game are told when to stop thinking.
+#[derive(Debug, Clone, Copy)]
+pub enum TimeControl {
+    /// the same budget for every move
+    PerMove(Budget),
+    /// a clock per side, shared out across the game
+    Clock(Clock),
+}
+
+impl TimeControl {
+    /// Budget for the next move of the side this time control belongs to.
+    pub fn budget(&self, board: &BoardState) -> Budget {
+        match self {
+            TimeControl::PerMove(budget) => *budget,
+            TimeControl::Clock(clock) => Budget::time(clock.allocate(board)),
+        }
+    }
+
+    pub fn spend(&mut self, used: Duration) {
+        if let TimeControl::Clock(clock) = self {
+            clock.spend(used);
+        }
+    }
+}
+
+/// Time left for one side, with an increment added back after every move.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    remaining: Duration,
+    increment: Duration,
+}
+
+impl Clock {
+    pub fn new(total: Duration, increment: Duration) -> Self {
+        Self { remaining: total, increment }
+    }
+
+    /// Time to spend on the next move: an even share of the clock over the moves likely left,
+    /// plus most of the increment, but never more than half of what is left with the increment
+    /// this move earns.
+    pub fn allocate(&self, board: &BoardState) -> Duration {
+        // assume about half the empty cells will be filled by us
+        let empty = board.geometry().all.difference(&board.occupied()).count();
+        let moves_to_go = (empty / 2).max(MIN_MOVES_TO_GO);
+
+        let share = (self.remaining / moves_to_go).saturating_add(self.increment * 3 / 4);
+
+        share.min(self.remaining.saturating_add(self.increment) / 2)
+    }
+
+    /// Charges a finished move to the clock and adds the increment.
+    pub fn spend(&mut self, used: Duration) {
+        self.remaining = self.remaining.saturating_sub(used).saturating_add(self.increment);
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2?} left", self.remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Clock;
+    use crate::board::BoardState;
+
+    fn seconds(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn the_clock_is_shared_over_the_moves_left() {
+        // 61 empty cells on size 5 leave about 30 moves to go, the 7 of size 2 the minimum of 10
+        let clock = Clock::new(seconds(300.0), Duration::ZERO);
+
+        assert_eq!(clock.allocate(&BoardState::new(5)), seconds(10.0));
+        assert_eq!(clock.allocate(&BoardState::new(2)), seconds(30.0));
+    }
+
+    #[test]
+    fn the_increment_is_used_even_on_an_empty_clock() {
+        assert_eq!(Clock::new(Duration::ZERO, seconds(5.0)).allocate(&BoardState::new(5)), seconds(2.5));
+        assert_eq!(Clock::new(seconds(3.0), seconds(2.0)).allocate(&BoardState::new(5)), seconds(1.6));
+    }
+
+    #[test]
+    fn spending_charges_the_move_and_adds_the_increment() {
+        let mut clock = Clock::new(seconds(10.0), seconds(2.0));
+
+        clock.spend(seconds(3.0));
+        assert_eq!(clock.remaining, seconds(9.0));
+
+        // running over leaves only the increment, never a negative clock
+        clock.spend(seconds(60.0));
+        assert_eq!(clock.remaining, seconds(2.0));
+    }
+}
@@ -0,0 +1,259 @@
+use std::{fs, path::Path, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{mcts::{BetaSchedule, Budget, MoveSelection, ParallelMode, ProgressiveWidening}, playout::Playout};
+
+/// Every setting of an `MCTS` engine. Build one from `default()` with the chained setters,
+/// or load it from a TOML or JSON file in which any field left out keeps its default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MctsConfig {
+    pub exploration_constant: f32,
+    /// iterations per search, per thread with root parallelism
+    pub iterations: Option<u32>,
+    /// wall-clock milliseconds per search
+    pub time_ms: Option<u64>,
+    /// stop a search once the tree holds this many nodes, whatever is left of the budget
+    pub max_nodes: Option<usize>,
+    pub threads: usize,
+    /// how more than one thread shares the work
+    pub parallel_mode: ParallelMode,
+    /// seed for the engine's random choices, fresh entropy when unset; with an iteration
+    /// budget and root parallelism the same seed replays the same searches and moves
+    pub seed: Option<u64>,
+    pub move_selection: MoveSelection,
+    pub transpositions: bool,
+    /// RAVE with this schedule, or plain UCT when unset, written `"off"` in a file
+    #[serde(with = "rave_setting")]
+    pub rave: Option<BetaSchedule>,
+    /// limit how many moves of a node are tried as its visits grow, for large boards
+    pub widening: Option<ProgressiveWidening>,
+    pub playout: Playout,
+    /// print search statistics after every move
+    pub verbose: bool,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            exploration_constant: 2.0_f32.sqrt(),
+            iterations: Some(1000),
+            time_ms: None,
+            max_nodes: None,
+            threads: 1,
+            parallel_mode: ParallelMode::Root,
+            seed: None,
+            move_selection: MoveSelection::MostVisits,
+            transpositions: false,
+            rave: Some(BetaSchedule::Equivalence(1000.0)),
+            widening: None,
+            playout: Playout::Uniform,
+            verbose: true,
+        }
+    }
+}
+
+impl MctsConfig {
+    pub fn exploration_constant(mut self, exploration_constant: f32) -> Self {
+        self.exploration_constant = exploration_constant;
+        self
+    }
+
+    pub fn iterations(mut self, iterations: u32) -> Self {
+        self.iterations = Some(iterations);
+        self
+    }
+
+    pub fn time(mut self, time: Duration) -> Self {
+        self.time_ms = Some(time.as_millis() as u64);
+        self
+    }
+
+    pub fn max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    pub fn threads(mut self, threads: usize, parallel_mode: ParallelMode) -> Self {
+        self.threads = threads;
+        self.parallel_mode = parallel_mode;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn move_selection(mut self, move_selection: MoveSelection) -> Self {
+        self.move_selection = move_selection;
+        self
+    }
+
+    pub fn transpositions(mut self, transpositions: bool) -> Self {
+        self.transpositions = transpositions;
+        self
+    }
+
+    pub fn rave(mut self, schedule: BetaSchedule) -> Self {
+        self.rave = Some(schedule);
+        self
+    }
+
+    pub fn widening(mut self, coefficient: f32, exponent: f32) -> Self {
+        self.widening = Some(ProgressiveWidening { coefficient, exponent });
+        self
+    }
+
+    pub fn playout(mut self, playout: Playout) -> Self {
+        self.playout = playout;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Iteration and time limits as a search budget. Without either a search runs one iteration.
+    pub fn budget(&self) -> Budget {
+        let time = self.time_ms.map(Duration::from_millis);
+
+        match (self.iterations, time) {
+            (Some(iterations), Some(time)) => Budget::both(iterations, time),
+            (Some(iterations), None) => Budget::iterations(iterations),
+            (None, Some(time)) => Budget::time(time),
+            (None, None) => Budget::iterations(1),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Reads a `.toml` or `.json` file, going by the extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => Err(format!("{}: expected a .toml or .json file", path.display())),
+        }
+    }
+}
+
+/// `rave` as a file spells it: a schedule, or `"off"` for plain UCT, since TOML has no null
+/// and leaving the field out keeps the default schedule.
+mod rave_setting {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::mcts::BetaSchedule;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Off {
+        Off,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Off(Off),
+        On(BetaSchedule),
+    }
+
+    pub fn serialize<S: Serializer>(rave: &Option<BetaSchedule>, serializer: S) -> Result<S::Ok, S::Error> {
+        match *rave {
+            Some(schedule) => Setting::On(schedule),
+            None => Setting::Off(Off::Off),
+        }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<BetaSchedule>, D::Error> {
+        Ok(match Setting::deserialize(deserializer)? {
+            Setting::On(schedule) => Some(schedule),
+            Setting::Off(Off::Off) => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MctsConfig;
+    use crate::{mcts::{BetaSchedule, MoveSelection, ParallelMode}, playout::Playout};
+
+    /// A config with every field away from its default.
+    fn tuned() -> MctsConfig {
+        MctsConfig::default()
+            .exploration_constant(0.7)
+            .iterations(5000)
+            .time(std::time::Duration::from_millis(1500))
+            .max_nodes(100_000)
+            .threads(4, ParallelMode::Tree)
+            .seed(42)
+            .move_selection(MoveSelection::Secure)
+            .transpositions(true)
+            .rave(BetaSchedule::Equivalence(300.0))
+            .widening(2.0, 0.5)
+            .playout(Playout::Heavy)
+            .verbose(false)
+    }
+
+    #[test]
+    fn toml_round_trips() {
+        let text = "
+            exploration_constant = 0.7
+            iterations = 5000
+            time_ms = 1500
+            max_nodes = 100000
+            threads = 4
+            parallel_mode = \"tree\"
+            seed = 42
+            move_selection = \"secure\"
+            transpositions = true
+            rave = { equivalence = 300.0 }
+            widening = { coefficient = 2.0, exponent = 0.5 }
+            playout = \"heavy\"
+            verbose = false
+        ";
+
+        assert_eq!(MctsConfig::from_toml(text), Ok(tuned()));
+        assert_eq!(MctsConfig::from_toml(&toml::to_string(&tuned()).unwrap()), Ok(tuned()));
+        assert!(MctsConfig::from_toml("iteration = 5000").is_err());
+
+        let plain_uct = MctsConfig { rave: None, ..MctsConfig::default() };
+        assert_eq!(MctsConfig::from_toml("rave = \"off\""), Ok(plain_uct.clone()));
+        assert_eq!(MctsConfig::from_toml(&toml::to_string(&plain_uct).unwrap()), Ok(plain_uct));
+        assert!(MctsConfig::from_toml("rave = \"on\"").is_err());
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let text = r#"{
+            "exploration_constant": 0.7,
+            "iterations": 5000,
+            "time_ms": 1500,
+            "max_nodes": 100000,
+            "threads": 4,
+            "parallel_mode": "tree",
+            "seed": 42,
+            "move_selection": "secure",
+            "transpositions": true,
+            "rave": { "equivalence": 300.0 },
+            "widening": { "coefficient": 2.0, "exponent": 0.5 },
+            "playout": "heavy",
+            "verbose": false
+        }"#;
+
+        assert_eq!(MctsConfig::from_json(text), Ok(tuned()));
+        assert_eq!(MctsConfig::from_json(&serde_json::to_string(&tuned()).unwrap()), Ok(tuned()));
+        assert!(MctsConfig::from_json(r#"{ "widening": { "coefficient": 2.0, "exponent": 0.5, "base": 1 } }"#).is_err());
+    }
+}
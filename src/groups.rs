@@ -0,0 +1,171 @@
+use crate::bitboard::{Bitboard, Geometry, MAX_CELLS, NO_CELL};
+
+/// Disjoint-set forest over the cells of a board, maintained incrementally as stones are placed.
+///
+/// Both players share one forest: stones are only ever joined with same-coloured neighbours,
+/// so a set never mixes players. Every root carries bitmasks of the corners and edge sides
+/// its group touches, which turns bridge and fork checks into a popcount, and the cells next
+/// to the group, which are its empty neighbours once the occupied cells are taken out.
+///
+/// Storage is fixed-size apart from the undo log. There is no path compression, so every
+/// change is a handful of slot writes that `rollback` can replay in reverse; union by size
+/// keeps the trees shallow enough without it.
+#[derive(Debug, Clone)]
+pub struct Groups {
+    parent: [u16; MAX_CELLS],
+    size: [u16; MAX_CELLS],
+    corners: [u8; MAX_CELLS],
+    sides: [u8; MAX_CELLS],
+    adjacent: [Bitboard; MAX_CELLS],
+    /// previous contents of every slot written, oldest first
+    log: Vec<Slot>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    cell: u16,
+    parent: u16,
+    size: u16,
+    corners: u8,
+    sides: u8,
+    adjacent: Bitboard,
+}
+
+impl Groups {
+    pub fn new() -> Self {
+        Self {
+            parent: std::array::from_fn(|i| i as u16),
+            size: [0; MAX_CELLS],
+            corners: [0; MAX_CELLS],
+            sides: [0; MAX_CELLS],
+            adjacent: [Bitboard::default(); MAX_CELLS],
+            log: vec![],
+        }
+    }
+
+    fn save(&mut self, cell: usize) {
+        self.log.push(Slot {
+            cell: cell as u16,
+            parent: self.parent[cell],
+            size: self.size[cell],
+            corners: self.corners[cell],
+            sides: self.sides[cell],
+            adjacent: self.adjacent[cell],
+        });
+    }
+
+    /// Position in the undo log to pass to `rollback` later.
+    pub fn mark(&self) -> usize {
+        self.log.len()
+    }
+
+    /// Restores every slot written since `mark` was taken.
+    pub fn rollback(&mut self, mark: usize) {
+        while self.log.len() > mark {
+            let slot = self.log.pop().unwrap();
+            let cell = slot.cell as usize;
+
+            self.parent[cell] = slot.parent;
+            self.size[cell] = slot.size;
+            self.corners[cell] = slot.corners;
+            self.sides[cell] = slot.sides;
+            self.adjacent[cell] = slot.adjacent;
+        }
+    }
+
+    /// Registers a freshly placed stone as its own group.
+    pub fn add(&mut self, geometry: &Geometry, cell: usize) {
+        self.save(cell);
+
+        self.parent[cell] = cell as u16;
+        self.size[cell] = 1;
+        self.corners[cell] = geometry.corner_bits[cell];
+        self.sides[cell] = geometry.side_bits[cell];
+        self.adjacent[cell] = geometry.neighbour_masks[cell];
+    }
+
+    pub fn find(&self, cell: usize) -> usize {
+        let mut root = cell;
+        while self.parent[root] as usize != root {
+            root = self.parent[root] as usize;
+        }
+
+        root
+    }
+
+    /// Merges the groups of two stones, returning the surviving root.
+    pub fn union(&mut self, a: usize, b: usize) -> usize {
+        let mut root_a = self.find(a);
+        let mut root_b = self.find(b);
+
+        if root_a == root_b {
+            return root_a;
+        }
+
+        if self.size[root_a] < self.size[root_b] {
+            std::mem::swap(&mut root_a, &mut root_b);
+        }
+
+        self.save(root_a);
+        self.save(root_b);
+
+        self.parent[root_b] = root_a as u16;
+        self.size[root_a] += self.size[root_b];
+        self.corners[root_a] |= self.corners[root_b];
+        self.sides[root_a] |= self.sides[root_b];
+        self.adjacent[root_a] = self.adjacent[root_a].union(&self.adjacent[root_b]);
+
+        root_a
+    }
+
+    /// True when two same-coloured neighbours of `cell` that do not touch each other already
+    /// belong to one group, i.e. placing a stone on `cell` closes a loop that may form a ring.
+    pub fn closes_loop(&self, geometry: &Geometry, cell: usize, is_own: impl Fn(usize) -> bool) -> bool {
+        Self::loop_among(&self.neighbour_roots(geometry, cell, is_own))
+    }
+
+    /// Root of the group of every neighbour of `cell` that `is_own` accepts, in
+    /// `NEIGHBOUR_OFFSETS` order.
+    pub fn neighbour_roots(&self, geometry: &Geometry, cell: usize, is_own: impl Fn(usize) -> bool) -> [Option<usize>; 6] {
+        geometry.neighbours[cell].map(|n| (n != NO_CELL && is_own(n as usize)).then(|| self.find(n as usize)))
+    }
+
+    /// `closes_loop` on the roots `neighbour_roots` found.
+    pub fn loop_among(roots: &[Option<usize>; 6]) -> bool {
+        for i in 0..6 {
+            for j in (i + 2)..6 {
+                // first and last offsets are adjacent as well
+                if i == 0 && j == 5 { continue; }
+
+                if let (Some(a), Some(b)) = (roots[i], roots[j]) && a == b {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Corners touched by the group with this root, one bit each as in `Geometry::corner_bits`.
+    pub fn corners(&self, root: usize) -> u8 {
+        self.corners[root]
+    }
+
+    /// Edge sides touched by the group with this root, one bit each as in `Geometry::side_bits`.
+    pub fn sides(&self, root: usize) -> u8 {
+        self.sides[root]
+    }
+
+    /// Cells next to a stone of the group with this root, its own stones among them.
+    pub fn adjacent(&self, root: usize) -> &Bitboard {
+        &self.adjacent[root]
+    }
+
+    pub fn corner_count(&self, root: usize) -> u32 {
+        self.corners[root].count_ones()
+    }
+
+    pub fn side_count(&self, root: usize) -> u32 {
+        self.sides[root].count_ones()
+    }
+}
@@ -0,0 +1,340 @@
+use std::{io::{self, BufRead, Write}, num::IntErrorKind, time::{Duration, Instant}};
+
+use crate::{
+    bitboard::MAX_BOARD_SIZE,
+    board::{BoardState, GameResult, Notation, Player},
+    clock::{Clock, TimeControl},
+    config::MctsConfig,
+    mcts::{Budget, MCTS},
+    record::GameRecord,
+    report::SearchReport,
+};
+
+const COMMANDS: [&str; 19] = [
+    "protocol_version",
+    "name",
+    "version",
+    "known_command",
+    "list_commands",
+    "quit",
+    "boardsize",
+    "clear_board",
+    "play",
+    "genmove",
+    "undo",
+    "time_settings",
+    "final_score",
+    "showboard",
+    "notation",
+    "loadsgf",
+    "analyze",
+    "principal_variation",
+    "win_probability",
+];
+
+/// The engine behind a GTP-style text protocol, for GUIs and match runners.
+///
+/// Every command is one line, optionally led by a numeric id, and is answered with
+/// `=[id] response` or `?[id] error` followed by an empty line. The first player is black
+/// (`b`), the second white (`w`). Moves are read as in the interactive modes, `e5`, `q r`,
+/// `(q, r)` or `swap`, and written in the board's notation. Search diagnostics go to stderr so they never mix with the responses.
+pub struct Engine {
+    board: BoardState,
+    config: MctsConfig,
+    mcts: MCTS,
+    /// per side, indexed by `Player::index`
+    time_controls: [TimeControl; 2],
+}
+
+impl Engine {
+    pub fn new(board: BoardState, config: MctsConfig) -> Self {
+        let time_control = TimeControl::PerMove(config.budget());
+
+        Self {
+            board,
+            mcts: MCTS::with_config(config.clone().verbose(false)),
+            config,
+            time_controls: [time_control; 2],
+        }
+    }
+
+    /// Answers commands from `input` until it runs out or a `quit` arrives.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            let line = line.split('#').next().unwrap().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.split_whitespace().peekable();
+            let id = words.next_if(|word| word.chars().all(|c| c.is_ascii_digit())).unwrap_or("");
+            let Some(command) = words.next() else { continue };
+            let args: Vec<&str> = words.collect();
+
+            match self.execute(command, &args) {
+                // an empty line ends a response, so multi-line ones must not end in a newline
+                Ok(response) => write!(output, "={} {}\n\n", id, response.trim_end())?,
+                Err(e) => write!(output, "?{} {}\n\n", id, e)?,
+            }
+            output.flush()?;
+
+            if command == "quit" {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, command: &str, args: &[&str]) -> Result<String, &'static str> {
+        match (command, args) {
+            ("protocol_version", []) => Ok("2".to_string()),
+            ("name", []) => Ok(env!("CARGO_PKG_NAME").to_string()),
+            ("version", []) => Ok(env!("CARGO_PKG_VERSION").to_string()),
+            ("known_command", [name]) => Ok(COMMANDS.contains(name).to_string()),
+            ("list_commands", []) => Ok(COMMANDS.join("\n")),
+            ("quit", []) => Ok(String::new()),
+
+            ("boardsize", [size]) => {
+                let size: i8 = size.parse().map_err(|_| "board size must be a number")?;
+
+                if !(2..=MAX_BOARD_SIZE).contains(&size) {
+                    return Err("unacceptable size");
+                }
+
+                self.clear_board(size);
+                Ok(String::new())
+            }
+            ("clear_board", []) => {
+                self.clear_board(self.board.board_size);
+                Ok(String::new())
+            }
+            ("play", [color, m @ ..]) => {
+                self.expect_turn(color)?;
+
+                if self.board.is_terminal() {
+                    return Err("the game is over");
+                }
+
+                let m = self.board.parse_move(&m.join(" "))?;
+                self.board.apply_move(m)?;
+                self.mcts.advance(m);
+
+                Ok(String::new())
+            }
+            ("genmove", [color]) => {
+                self.expect_turn(color)?;
+
+                if self.board.is_terminal() {
+                    return Err("the game is over");
+                }
+
+                let side = self.board.turn.index();
+                let start = Instant::now();
+                let report = self.search(self.time_controls[side].budget(&self.board));
+                self.time_controls[side].spend(start.elapsed());
+
+                self.board.apply_move(report.best_move).unwrap();
+                self.mcts.advance(report.best_move);
+
+                Ok(self.board.format_move(report.best_move))
+            }
+            ("undo", []) => {
+                // the search tree no longer matches and is rebuilt on the next search
+                self.board.undo_move()?;
+                Ok(String::new())
+            }
+            ("time_settings", [main_time, byo_yomi_time, byo_yomi_stones]) => {
+                // whole seconds that fit in a u32 leave the clock arithmetic far from overflowing
+                let parse = |s: &str| s.parse::<u32>().map_err(|e| match e.kind() {
+                    IntErrorKind::PosOverflow => "time settings out of range",
+                    _ => "time settings must be whole numbers",
+                });
+                let (main_time, byo_yomi_time, byo_yomi_stones) = (parse(main_time)?, parse(byo_yomi_time)?, parse(byo_yomi_stones)?);
+                let seconds = |s: u32| Duration::from_secs(s.into());
+
+                let time_control = match (main_time, byo_yomi_time, byo_yomi_stones) {
+                    // no time limit, the configured budget applies
+                    (0, 0, _) | (_, 1.., 0) => TimeControl::PerMove(self.config.budget()),
+                    // pure byo-yomi: every move gets its share of the current period
+                    (0, period, stones) => TimeControl::PerMove(Budget::time(seconds(period) / stones)),
+                    // a byo-yomi period is spread out as an increment per stone
+                    (main_time, period, stones) => TimeControl::Clock(Clock::new(seconds(main_time), seconds(period) / stones.max(1))),
+                };
+
+                self.time_controls = [time_control; 2];
+                Ok(String::new())
+            }
+            ("final_score", []) => match self.board.result() {
+                GameResult::Win(Player::P1) => Ok("B+".to_string()),
+                GameResult::Win(Player::P2) => Ok("W+".to_string()),
+                GameResult::Draw => Ok("0".to_string()),
+                GameResult::Ongoing => Err("the game is not over"),
+            },
+            ("showboard", []) => Ok(format!("\n{}", self.board)),
+            // the position before `move_number` is played, after the whole game without it
+            ("loadsgf", [path, move_number @ ..]) if move_number.len() <= 1 => {
+                let ply = match move_number {
+                    [number] => number.parse::<usize>().ok().filter(|&n| n >= 1).ok_or("move number must be positive")? - 1,
+                    _ => usize::MAX,
+                };
+                let record = GameRecord::load(path).map_err(|e| {
+                    eprintln!("{}", e);
+                    "cannot load file"
+                })?;
+
+                self.new_game(record.board_at(ply));
+                Ok(String::new())
+            }
+            ("notation", [name]) => {
+                self.board.notation = match name.to_ascii_lowercase().as_str() {
+                    "standard" => Notation::Standard,
+                    "axial" => Notation::Axial,
+                    _ => return Err("notation must be standard or axial"),
+                };
+                Ok(String::new())
+            }
+
+            // searches the current position within the configured budget without playing
+            ("analyze", []) => Ok(format!("\n{}", self.analyze()?)),
+            ("principal_variation", []) => {
+                let report = self.analyze()?;
+                let line: Vec<String> = report.principal_variation.iter().map(|&m| self.board.format_move(m)).collect();
+                Ok(line.join(" "))
+            }
+            ("win_probability", []) => Ok(format!("{:.3}", self.analyze()?.win_probability)),
+
+            (command, _) if COMMANDS.contains(&command) => Err("wrong number of arguments"),
+            _ => Err("unknown command"),
+        }
+    }
+
+    fn clear_board(&mut self, size: i8) {
+        self.new_game(BoardState::with_rules(size, self.board.rules));
+    }
+
+    /// Replaces the position, keeping the notation, with a search tree to match.
+    fn new_game(&mut self, mut board: BoardState) {
+        board.notation = self.board.notation;
+        self.board = board;
+        self.mcts = MCTS::with_config(self.config.clone().verbose(false));
+    }
+
+    fn expect_turn(&self, color: &str) -> Result<(), &'static str> {
+        let player = match color.to_ascii_lowercase().as_str() {
+            "b" | "black" => Player::P1,
+            "w" | "white" => Player::P2,
+            _ => return Err("invalid color"),
+        };
+
+        if player != self.board.turn {
+            return Err("it is the other color's turn");
+        }
+
+        Ok(())
+    }
+
+    fn analyze(&mut self) -> Result<SearchReport, &'static str> {
+        if self.board.is_terminal() {
+            return Err("the game is over");
+        }
+
+        Ok(self.search(self.config.budget()))
+    }
+
+    fn search(&mut self, budget: Budget) -> SearchReport {
+        self.mcts.set_budget(budget);
+        let report = self.mcts.analyze(self.board.clone());
+
+        if self.config.verbose {
+            eprint!("{}", report);
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Engine;
+    use crate::{board::BoardState, config::MctsConfig, mcts::Budget};
+
+    /// A fresh size-5 engine after `commands`, one command per line, with its responses.
+    fn engine_after(commands: &str) -> (Engine, String) {
+        let config = MctsConfig::default().iterations(50).seed(1);
+        let mut engine = Engine::new(BoardState::new(5), config);
+        let mut output = vec![];
+
+        engine.run(commands.as_bytes(), &mut output).unwrap();
+        (engine, String::from_utf8(output).unwrap())
+    }
+
+    /// The responses to `commands` from a fresh size-5 engine.
+    fn session(commands: &str) -> String {
+        engine_after(commands).1
+    }
+
+    #[test]
+    fn responses_carry_the_command_id() {
+        assert_eq!(
+            session("1 protocol_version\n2 play x e5\nfly\n3 boardsize 11\n# a comment\n4 known_command undo\n"),
+            "=1 2\n\n?2 invalid color\n\n? unknown command\n\n?3 unacceptable size\n\n=4 true\n\n",
+        );
+    }
+
+    #[test]
+    fn undo_takes_back_the_last_move() {
+        assert_eq!(
+            session("play b e5\nplay w e5\nplay w d4\nundo\nplay b d3\nplay w d4\nundo\nundo\nundo\n"),
+            "= \n\n? cell already occupied\n\n= \n\n= \n\n? it is the other color's turn\n\n= \n\n= \n\n= \n\n? no moves to undo\n\n",
+        );
+    }
+
+    #[test]
+    fn a_new_board_size_gets_a_fresh_search() {
+        // the empty boards of both sizes hash the same
+        let output = session("analyze\nboardsize 3\n1 genmove b\n");
+        let reply = output.rsplit("\n\n").nth(1).unwrap();
+        let m = reply.strip_prefix("=1 ").unwrap();
+
+        assert!(BoardState::new(3).parse_move(m).is_ok(), "{} is not on a size-3 board", m);
+    }
+
+    #[test]
+    fn the_score_is_given_once_the_game_is_over() {
+        // on a size-2 board every edge cell is a corner, so two neighbouring ones make a bridge
+        assert_eq!(
+            session("boardsize 2\nfinal_score\nplay b c2\nplay w a2\nplay b c3\nfinal_score\nplay w b2\ngenmove w\n"),
+            "= \n\n? the game is not over\n\n= \n\n= \n\n= \n\n= B+\n\n? the game is over\n\n? the game is over\n\n",
+        );
+    }
+
+    #[test]
+    fn pure_byo_yomi_gives_every_move_its_share_of_a_period() {
+        let (mut engine, output) = engine_after("time_settings 0 30 5\n");
+        assert_eq!(output, "= \n\n");
+
+        for _ in 0..3 {
+            assert_eq!(engine.time_controls[0].budget(&engine.board), Budget::time(Duration::from_secs(6)));
+            engine.time_controls[0].spend(Duration::from_secs(6));
+        }
+    }
+
+    #[test]
+    fn time_settings_out_of_range_are_refused() {
+        let (mut engine, output) = engine_after(
+            "time_settings 10 30 4294967296\ntime_settings 10 18446744073709551615 1\ntime_settings 4294967295 4294967295 1\n",
+        );
+        assert_eq!(output, "? time settings out of range\n\n? time settings out of range\n\n= \n\n");
+
+        // the largest settings accepted still run the clock without overflowing
+        for _ in 0..100 {
+            engine.time_controls[0].budget(&engine.board);
+            engine.time_controls[0].spend(Duration::ZERO);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::time::Duration;
+
+use crate::{
+    bitboard::MAX_BOARD_SIZE,
+    board::{BoardState, HexOwner, Move, Player},
+    record::{GameRecord, MoveStats, PlayerInfo, RecordedMove},
+};
+
+/// Turns the console output of AI-vs-AI games, as kept in `games/`, into game records.
+///
+/// The logs mix boards drawn by `print_state_pretty` with `AI 1 plays: (q, r) in 3.11s`,
+/// `looked through N moves` and `AI 1 won` lines, and games may be headed by `GAME n`. The
+/// moves are replayed and checked against every board drawn. Stones a board shows without
+/// a move announcing them, such as an opening stone placed by hand, become moves without a
+/// time. The look-through count of a search is kept as its node count. Lines of any other
+/// kind are skipped.
+///
+/// The engine that wrote the logs printed the time of the first AI's move again on every
+/// `AI 2 plays` line, so the second AI's moves are kept without a time.
+///
+/// The engine that wrote the logs counted a corner as an edge cell of one of its sides, so
+/// some of its games end on a fork that is not one. Such a game is kept as unfinished.
+pub fn import_log(text: &str) -> Result<Vec<GameRecord>, String> {
+    let mut import = Import::default();
+
+    for (i, line) in text.lines().enumerate() {
+        import.line(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+    }
+
+    import.flush_board().map_err(|e| format!("end of log: {}", e))?;
+    import.end_game();
+
+    Ok(import.records)
+}
+
+#[derive(Default)]
+struct Import {
+    records: Vec<GameRecord>,
+    game: Option<Game>,
+    /// rows of the board being read
+    rows: Vec<Vec<HexOwner>>,
+    /// node count of the search whose move comes next
+    nodes: Option<usize>,
+}
+
+struct Game {
+    board: BoardState,
+    record: GameRecord,
+    /// the side each AI plays, indexed by its number less one, once it has moved
+    sides: [Option<Player>; 2],
+}
+
+impl Import {
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+
+        if let Some(row) = board_row(line) {
+            self.rows.push(row);
+            return Ok(());
+        }
+
+        self.flush_board()?;
+
+        if line.starts_with("GAME ") {
+            self.end_game();
+        } else if let Some(count) = line.strip_prefix("looked through ").and_then(|rest| rest.strip_suffix(" moves")) {
+            self.nodes = Some(count.parse().map_err(|_| "expected a number of moves")?);
+        } else if let Some(rest) = line.strip_prefix("AI ") {
+            let (number, rest) = rest.split_once(' ').ok_or("expected an AI number")?;
+            let ai = match number.trim_end_matches(':') {
+                "1" => 0,
+                "2" => 1,
+                _ => return Err(format!("unknown AI {}", number)),
+            };
+
+            if let Some(rest) = rest.strip_prefix("plays: ") {
+                self.play(ai, rest)?;
+            } else if rest == "won" {
+                self.won(ai)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn play(&mut self, ai: usize, announcement: &str) -> Result<(), String> {
+        let game = self.game.as_mut().ok_or("a move before the first board")?;
+        let (m, rest) = announcement.split_once(" in ").ok_or("expected the time a move took")?;
+        let time = rest.split_whitespace().next().and_then(parse_duration).ok_or("expected the time a move took")?;
+
+        if game.board.is_terminal() {
+            return Err("a move after the game ended".to_string());
+        }
+
+        let side = *game.sides[ai].get_or_insert(game.board.turn);
+        if side != game.board.turn {
+            return Err(format!("AI {} plays out of turn", ai + 1));
+        }
+        game.record.players[side.index()].name = format!("AI {}", ai + 1);
+
+        let m = game.board.parse_move(m).and_then(|m| game.board.apply_move(m))?;
+        let stats = self.nodes.take().map(|nodes| MoveStats { nodes: Some(nodes), ..MoveStats::default() });
+        let time = (ai == 0).then_some(time);
+        game.record.moves.push(RecordedMove { m, time, stats });
+
+        Ok(())
+    }
+
+    fn won(&mut self, ai: usize) -> Result<(), String> {
+        let game = self.game.as_ref().ok_or("a result before the first board")?;
+
+        match (game.board.win_info(), game.sides[ai]) {
+            (Some(win), Some(side)) if win.player == side => Ok(()),
+            (None, Some(side)) if corner_fork(&game.board, side) => Ok(()),
+            _ => Err(format!("the moves do not give AI {} the win", ai + 1)),
+        }
+    }
+
+    /// Checks the board just read against the game so far, starting a new game when it
+    /// shows a fresh position after a finished one.
+    fn flush_board(&mut self) -> Result<(), String> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let rows = std::mem::take(&mut self.rows);
+        let board_size = rows.len().div_ceil(2) as i8;
+
+        if rows.len().is_multiple_of(2) || !(2..=MAX_BOARD_SIZE).contains(&board_size) {
+            return Err(format!("a board of {} rows", rows.len()));
+        }
+
+        let n = board_size as i32 - 1;
+        let mut shown = vec![];
+
+        for (i, row) in rows.iter().enumerate() {
+            let r = i as i32 - n;
+            let q_min = (-n).max(-r - n);
+            let q_max = n.min(-r + n);
+
+            if row.len() as i32 != q_max - q_min + 1 {
+                return Err(format!("board row {} has the wrong length", i + 1));
+            }
+
+            shown.extend(row.iter().zip(q_min..).map(|(&owner, q)| ((q, r), owner)));
+        }
+
+        let follows = |game: &Game| {
+            game.board.board_size == board_size
+                && shown.iter().all(|&((q, r), owner)| [HexOwner::None, owner].contains(&game.board.owner_at(q, r)))
+        };
+
+        match &self.game {
+            Some(game) if follows(game) => {},
+            Some(game) if !game.board.is_terminal() => return Err("the board does not follow from the moves".to_string()),
+            _ => {
+                self.end_game();
+                let board = BoardState::new(board_size);
+                let players = [PlayerInfo::human("Black"), PlayerInfo::human("White")];
+                self.game = Some(Game { record: GameRecord::new(&board, players), board, sides: [None; 2] });
+            },
+        }
+
+        let game = self.game.as_mut().unwrap();
+
+        // stones nobody announced, played in turn
+        loop {
+            let missing: Vec<(i32, i32)> = shown.iter()
+                .filter(|&&((q, r), owner)| owner == HexOwner::from(&game.board.turn) && game.board.owner_at(q, r) == HexOwner::None)
+                .map(|&(cell, _)| cell)
+                .collect();
+
+            let Some(&(q, r)) = missing.first() else { break };
+            let m = game.board.apply_move(Move::Place(q, r))?;
+            game.record.moves.push(RecordedMove { m, time: None, stats: None });
+        }
+
+        if shown.iter().any(|&((q, r), owner)| game.board.owner_at(q, r) != owner) {
+            return Err("the board does not follow from the moves".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn end_game(&mut self) {
+        if let Some(game) = self.game.take() && !game.record.moves.is_empty() {
+            self.records.push(game.record);
+        }
+    }
+}
+
+/// Whether a group of `player` touches three sides when a corner counts towards the first
+/// of the two sides it lies on, as it did for the engine that wrote the logs.
+fn corner_fork(board: &BoardState, player: Player) -> bool {
+    let geometry = board.geometry();
+    let side = |cell: usize| {
+        if geometry.corner_bits[cell] == 0 {
+            return geometry.side_bits[cell];
+        }
+
+        // the two sides of a corner are those of the edge cells next to it
+        let sides = geometry.neighbours_of(cell).fold(0u8, |sides, n| sides | geometry.side_bits[n]);
+        sides & sides.wrapping_neg()
+    };
+
+    let mut unseen = *board.stones(player);
+
+    loop {
+        let Some(start) = unseen.iter().next() else { break };
+        unseen.remove(start);
+
+        let mut group = vec![start];
+        let mut sides = 0;
+        let mut i = 0;
+
+        while i < group.len() {
+            sides |= side(group[i]);
+
+            for neighbour in geometry.neighbours_of(group[i]) {
+                if unseen.contains(neighbour) {
+                    unseen.remove(neighbour);
+                    group.push(neighbour);
+                }
+            }
+            i += 1;
+        }
+
+        if sides.count_ones() >= 3 {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// The cells of a line drawn by `print_state_pretty`, if it is one.
+fn board_row(line: &str) -> Option<Vec<HexOwner>> {
+    let row: Vec<HexOwner> = line.split_whitespace()
+        .map(|cell| match cell {
+            "." => Some(HexOwner::None),
+            "X" => Some(HexOwner::P1),
+            "O" => Some(HexOwner::P2),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    (!row.is_empty()).then_some(row)
+}
+
+/// Reads a duration as `{:.2?}` prints it, such as `25.39s` or `796.03ms`.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, unit) = text.split_at(text.find(|c: char| !c.is_ascii_digit() && c != '.')?);
+    let number: f64 = number.parse().ok()?;
+
+    let seconds = match unit {
+        "s" => number,
+        "ms" => number / 1e3,
+        "µs" => number / 1e6,
+        "ns" => number / 1e9,
+        _ => return None,
+    };
+
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::import_log;
+    use crate::{board::{GameResult, Move, Player}, record::GameRecord};
+
+    fn import(log: &str) -> Vec<GameRecord> {
+        import_log(log).unwrap()
+    }
+
+    fn winner(record: &GameRecord) -> (String, GameResult) {
+        let result = record.final_board().result();
+        let GameResult::Win(player) = result else { panic!("the game did not finish") };
+        (record.players[player.index()].name.clone(), result)
+    }
+
+    #[test]
+    fn archived_logs_replay_to_their_results() {
+        let multithreaded = import(include_str!("../games/multithreaded_size5_1500_iter.txt"));
+        let size_5 = import(include_str!("../games/unoptimized_size_5_10_000_iter.txt"));
+        let size_6 = import(include_str!("../games/unoptimized_size_6_100_iter.txt"));
+
+        assert_eq!(multithreaded.len(), 1);
+        assert_eq!((multithreaded[0].board_size, multithreaded[0].moves.len()), (5, 28));
+        // AI 2 was given a fork through the corner a5, which touches only two sides
+        assert_eq!(multithreaded[0].final_board().result(), GameResult::Ongoing);
+
+        assert_eq!((size_5[0].board_size, size_5[0].moves.len()), (5, 13));
+        assert_eq!(winner(&size_5[0]), ("AI 1".to_string(), GameResult::Win(Player::P1)));
+
+        assert_eq!((size_6[0].board_size, size_6[0].moves.len()), (6, 56));
+        assert_eq!(winner(&size_6[0]), ("AI 2".to_string(), GameResult::Win(Player::P2)));
+    }
+
+    #[test]
+    fn moves_keep_their_times_and_node_counts() {
+        let size_5 = import(include_str!("../games/unoptimized_size_5_10_000_iter.txt"));
+        let first = size_5[0].moves[0];
+
+        assert_eq!(first.m, Move::Place(0, 4));
+        assert_eq!(first.time, Some(Duration::from_secs_f64(112.35)));
+        assert_eq!(first.stats.and_then(|stats| stats.nodes), Some(583670));
+
+        // the log repeats the first AI's time for the second
+        let second = size_5[0].moves[1];
+        assert_eq!(second.m, Move::Place(-3, 0));
+        assert_eq!(second.time, None);
+        assert!(second.stats.and_then(|stats| stats.nodes).is_some());
+
+        // the multithreaded engine printed no search statistics
+        let multithreaded = import(include_str!("../games/multithreaded_size5_1500_iter.txt"));
+        assert!(multithreaded[0].moves.iter().all(|recorded| recorded.stats.is_none()));
+    }
+
+    #[test]
+    fn unannounced_stones_become_moves() {
+        let games = import(include_str!("../games/unoptimized_size_3_1000_iter.txt"));
+
+        assert_eq!(games.len(), 2);
+
+        // the first game opened with a stone in the centre that no line announces, so the
+        // first AI played second and won
+        assert_eq!(games[0].moves[0].m, Move::Place(0, 0));
+        assert_eq!(games[0].moves[0].time, None);
+        assert_eq!(games[0].moves[1].time, Some(Duration::from_secs_f64(3.11)));
+        assert_eq!(winner(&games[0]), ("AI 1".to_string(), GameResult::Win(Player::P2)));
+        assert_eq!(winner(&games[1]), ("AI 1".to_string(), GameResult::Win(Player::P1)));
+    }
+
+    #[test]
+    fn logs_contradicting_their_boards_are_rejected() {
+        let board = "  . . .\n . . . .\n. . . . .\n . . . .\n  . . .\n";
+
+        assert!(import_log(&format!("{}AI 1 plays: (0, 0) in 1.00s\nAI 1 plays: (1, 0) in 1.00s\n", board)).is_err());
+        assert!(import_log(&format!("{}AI 1 plays: (0, 0) in 1.00s\n{}", board, board)).is_err());
+        assert!(import_log(&format!("{}AI 1 plays: (0, 0) in 1.00s\nAI 1 won\n", board)).is_err());
+        assert!(import_log("AI 1 plays: (0, 0) in 1.00s\n").is_err());
+        assert!(import_log(&format!("{}AI 1 plays: (0, 0) in {}s\n", board, "9".repeat(400))).is_err());
+    }
+}
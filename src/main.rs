@@ -1,13 +1,32 @@
-
+mod bitboard;
 mod board;
+mod cli;
+mod clock;
+mod config;
+mod groups;
+mod gtp;
+mod import;
 mod win_detector;
 mod mcts;
+mod playout;
+mod record;
+mod report;
+
+use std::{env, process};
 
-use board::BoardState; 
-use win_detector::WinDetector;
+use cli::{Cli, USAGE};
 
 fn main() {
-    let mut board = BoardState::new(5);
-    board.start_game_ai_vs_ai(1500, Some(6));
-}
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
 
+    if let Err(e) = cli.run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -1,309 +1,1204 @@
 
-use core::panic;
-use std::collections::HashMap;
+use std::{cmp::Reverse, collections::{BTreeMap, HashMap, HashSet}, fmt, iter, sync::{atomic::{AtomicU32, AtomicU8, Ordering}, Mutex, RwLock}, thread, time::{Duration, Instant}};
 
-use crate::{board::{self, BoardState, Hex, Player}, win_detector};
-use rand::{Rng, rng};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use crate::{bitboard::Bitboard, board::{self, BoardState, GameResult, Move, Player, Rules}, config::MctsConfig, playout::{self, PlayoutPolicy}, report::{MoveReport, SearchReport}};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
 
+/// One position in the tree. Positions are not stored: the search replays the moves from the
+/// root on a working board instead, so a node is only its move, its links and its statistics.
 #[derive(Debug)]
 struct Node {
-    state: BoardState,
-    parent_index: Option<usize>,
-    children: Vec<usize>,
-    visits: u32,
-    total_reward: f32,
-    uct: f32,
+    parent_index: Option<u32>,
+    /// head of the list of children, linked through `next_sibling`; zero ends a list, as the
+    /// root is never anyone's child
+    first_child: u32,
+    next_sibling: u32,
+    child_count: u16,
+    /// Cells of the moves `expand` has not made into children yet, filled in the first time
+    /// the node is expanded, with `SWAP` standing for the swap move.
+    untried: Vec<u16>,
+    stats: AtomicStats,
+    /// descents through this node that have not been backed up yet, each counted as a lost
+    /// visit so that threads sharing the tree spread out over different lines
+    virtual_loss: AtomicU32,
     is_terminal: bool,
     player_to_move: board::Player,
-    last_move: Option<(i32, i32)>
+    last_move: Option<Move>,
+    hash: u64,
+    /// all-moves-as-first totals of `last_move`, from the point of view of the player making it
+    amaf: AtomicStats,
+    /// a `Proven` once solved, zero before
+    proven: AtomicU8,
+}
+
+/// Game-theoretic value of a node once the search has proven it, from the point of view of
+/// the player whose move led to it, like `total_reward`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Proven {
+    Win = 1,
+    Loss = 2,
+}
+
+impl Proven {
+    fn reward(self) -> f32 {
+        match self {
+            Proven::Win => 1.0,
+            Proven::Loss => -1.0,
+        }
+    }
+}
+
+/// Visit and reward totals for one position, shared by every node that reaches it.
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    visits: u32,
+    total_reward: f32,
+}
+
+/// `Stats` behind atomics, so threads sharing a tree can add to them without a lock.
+#[derive(Debug, Default)]
+struct AtomicStats {
+    visits: AtomicU32,
+    /// bits of an `f32`
+    total_reward: AtomicU32,
+}
+
+impl AtomicStats {
+    fn load(&self) -> Stats {
+        Stats {
+            visits: self.visits.load(Ordering::Relaxed),
+            total_reward: f32::from_bits(self.total_reward.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn add(&self, reward: f32) {
+        self.visits.fetch_add(1, Ordering::Relaxed);
+        // there is no atomic float add, so retry until no other thread got in between
+        let _ = self.total_reward.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f32::from_bits(bits) + reward).to_bits())
+        });
+    }
+}
+
+/// Number of separately locked parts of a `TranspositionTable`, a power of two.
+const TABLE_SHARDS: usize = 64;
+
+/// `Stats` keyed by position hash, split into shards by the low bits of the hash so that
+/// threads sharing a tree seldom wait on the same lock.
+#[derive(Debug)]
+struct TranspositionTable {
+    shards: Vec<Mutex<HashMap<u64, Stats>>>,
+}
+
+impl TranspositionTable {
+    fn new() -> Self {
+        Self { shards: (0..TABLE_SHARDS).map(|_| Mutex::new(HashMap::new())).collect() }
+    }
+
+    fn shard(&self, hash: u64) -> &Mutex<HashMap<u64, Stats>> {
+        &self.shards[hash as usize & (TABLE_SHARDS - 1)]
+    }
+
+    fn get(&self, hash: u64) -> Stats {
+        self.shard(hash).lock().unwrap().get(&hash).copied().unwrap_or_default()
+    }
+
+    fn add(&self, hash: u64, reward: f32) {
+        let mut shard = self.shard(hash).lock().unwrap();
+        let entry = shard.entry(hash).or_default();
+        entry.visits += 1;
+        entry.total_reward += reward;
+    }
+
+    fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(u64) -> bool) {
+        for shard in &mut self.shards {
+            shard.get_mut().unwrap().retain(|&hash, _| keep(hash));
+        }
+    }
 }
 
 impl Node {
-    pub fn new(state: BoardState, parent_index: Option<usize>, last_move: Option<(i32, i32)>) -> Self {
-        let is_terminal = is_terminal(&state);
-        let player_to_move = state.turn;
+    /// A node for the position on `board`, which is only looked at, not kept.
+    pub fn new(board: &BoardState, parent_index: Option<usize>, last_move: Option<Move>) -> Self {
+        let is_terminal = board.is_terminal();
+        let player_to_move = board.turn;
+        let hash = board.hash();
+        // a finished game was won by whoever moved last, a draw proves nothing either way
+        let proven = match board.result() {
+            GameResult::Win(_) => Proven::Win as u8,
+            _ => 0,
+        };
 
         Self  {
-            state,
-            parent_index,
-            children: vec![],
-            visits: 0,
-            total_reward: 0.0,
-            uct: 0.0,
+            parent_index: parent_index.map(|p| p as u32),
+            first_child: 0,
+            next_sibling: 0,
+            child_count: 0,
+            untried: vec![],
+            stats: AtomicStats::default(),
+            virtual_loss: AtomicU32::new(0),
             is_terminal,
-            player_to_move: player_to_move,
-            last_move
+            player_to_move,
+            last_move,
+            hash,
+            amaf: AtomicStats::default(),
+            proven: AtomicU8::new(proven),
         }
     }
+
+    fn parent(&self) -> Option<usize> {
+        self.parent_index.map(|p| p as usize)
+    }
+
+    fn proven(&self) -> Option<Proven> {
+        match self.proven.load(Ordering::Acquire) {
+            1 => Some(Proven::Win),
+            2 => Some(Proven::Loss),
+            _ => None,
+        }
+    }
+
+    fn prove(&self, proven: Proven) {
+        self.proven.store(proven as u8, Ordering::Release);
+    }
+}
+
+/// When a search stops: after a number of iterations, after a stretch of wall-clock time,
+/// or at whichever of the two comes first. Every search runs at least one iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    iterations: Option<u32>,
+    time: Option<Duration>,
 }
 
-fn is_terminal(state: &BoardState) -> bool {
-        let win_detector = win_detector::WinDetector::from_board(state);
-    
-        return win_detector.run(&board::Player::P1) || win_detector.run(&board::Player::P2)
+impl Budget {
+    pub fn iterations(iterations: u32) -> Self {
+        Self { iterations: Some(iterations), time: None }
+    }
+
+    pub fn time(time: Duration) -> Self {
+        Self { iterations: None, time: Some(time) }
+    }
+
+    pub fn both(iterations: u32, time: Duration) -> Self {
+        Self { iterations: Some(iterations), time: Some(time) }
+    }
+
+    fn exhausted(&self, iterations: u32, elapsed: Duration) -> bool {
+        self.iterations.is_some_and(|max| iterations >= max) || self.time.is_some_and(|max| elapsed >= max)
     }
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.iterations, self.time) {
+            (Some(i), Some(t)) => write!(f, "{} iterations or {:.2?}", i, t),
+            (Some(i), None) => write!(f, "{} iterations", i),
+            (None, Some(t)) => write!(f, "{:.2?}", t),
+            (None, None) => write!(f, "one iteration"),
+        }
+    }
+}
+
+/// Stands for `Move::Swap` among the untried cells of a node.
+const SWAP: u16 = u16::MAX;
+
+/// A root move with the statistics the move choice and the report are made from.
+#[derive(Debug, Clone, Copy)]
+struct RootChild {
+    mv: Move,
+    stats: Stats,
+    amaf: Stats,
+    proven: Option<Proven>,
+}
+
+/// How quickly RAVE hands a move over from its AMAF estimate to its own results.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BetaSchedule {
+    /// `sqrt(k / (3n + k))`: both estimates weigh the same once a move has `k` visits
+    Equivalence(f32),
+}
+
+impl BetaSchedule {
+    fn beta(self, visits: u32) -> f32 {
+        let n = visits as f32;
+
+        match self {
+            BetaSchedule::Equivalence(k) => (k / (3.0 * n + k)).sqrt(),
+        }
+    }
+}
+
+/// Caps the children of a node at `coefficient * visits^exponent`, so that on large boards
+/// the search looks deeper into a few moves before it has tried every one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProgressiveWidening {
+    pub coefficient: f32,
+    pub exponent: f32,
+}
 
+impl ProgressiveWidening {
+    fn max_children(self, visits: u32) -> usize {
+        (self.coefficient * (visits as f32).powf(self.exponent)).ceil().max(1.0) as usize
+    }
+}
+
+/// How `MCTS::run` shares a search out when it has more than one thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParallelMode {
+    /// a tree per thread, with the root statistics merged at the end
+    Root,
+    /// one tree shared by every thread, kept apart by virtual loss
+    Tree,
+}
+
+/// Which root child is played once the search is over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveSelection {
+    /// the robust child
+    MostVisits,
+    /// the max child
+    HighestMean,
+    /// the secure child, whose mean minus an exploration-sized margin is highest
+    Secure,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct MCTS {
     nodes: Vec<Node>,
-    exploration_constant: f32,
-    max_iter: i32,
+    config: MctsConfig,
+    budget: Budget,
+    /// Statistics keyed by position hash. When enabled, selection and the final move choice
+    /// read these instead of the per-node counters, so transpositions share what they learn.
+    transpositions: Option<TranspositionTable>,
+    /// Source of the seeds every search and worker thread draws its own generator from, so a
+    /// configured seed fixes the whole sequence of searches. Time budgets and tree
+    /// parallelism still make runs differ, since they depend on scheduling.
+    rng: StdRng,
+    /// one tree per thread for root parallelism, kept between moves like the main tree
+    workers: Vec<MCTS>,
+    /// board size and rules of the position at the root, which its hash does not cover
+    root_board: Option<(i8, Rules)>,
+    /// whether the running search shares the tree between threads, the only time virtual
+    /// loss is put on the nodes picked
+    shared: bool,
+}
+
+/// What `search` started from and how much it added.
+#[derive(Debug, Default, Clone, Copy)]
+struct Effort {
+    reused_nodes: usize,
+    reused_visits: u32,
+    playouts: u32,
 }
 
 impl MCTS {
-    pub fn new() -> Self {
+    pub fn with_config(config: MctsConfig) -> Self {
         Self {
             nodes: vec![],
-            exploration_constant: (2.0 as f32).sqrt(),
-            max_iter: 1000
+            budget: config.budget(),
+            transpositions: config.transpositions.then(TranspositionTable::new),
+            rng: match config.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_os_rng(),
+            },
+            workers: vec![],
+            root_board: None,
+            shared: false,
+            config,
         }
     }
 
-    pub fn run(&mut self, start_state: BoardState) -> (i32, i32) {
-        let root_index = self.search(start_state);
-        
-        return self.best_move(root_index);
+    /// Overrides the configured limits for the searches to come, as a time control does.
+    /// With root parallelism the budget applies to every thread, with tree parallelism to
+    /// the shared tree.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
     }
 
-    
-    pub fn run_parallel(&self, start_state: BoardState, threads: usize, iters: usize) -> (i32, i32) {
-        // each thread builds its own smaller tree
-        // for each thread return a hashmap which contains { move: visit count }  
-        let results: Vec<HashMap<(i32, i32), u32>> = (0..threads).into_par_iter().map(|_| {
-            
-            let mut local_mcts = MCTS {
-                nodes: vec![],
-                exploration_constant: self.exploration_constant,
-                max_iter: iters as i32,
-            };
+    pub fn config(&self) -> &MctsConfig {
+        &self.config
+    }
 
+    /// Searches `start_state` like `analyze`, printing the report when verbose.
+    pub fn run(&mut self, start_state: BoardState) -> SearchReport {
+        let report = self.analyze(start_state);
 
-            let root_index = local_mcts.search(start_state.clone());
+        if self.config.verbose {
+            print!("{}", report);
+        }
 
-            let root = &local_mcts.nodes[root_index];
+        report
+    }
 
-            let mut map = HashMap::new();
+    /// Searches `start_state` within the budget and reports on the root moves.
+    pub fn analyze(&mut self, start_state: BoardState) -> SearchReport {
+        let threads = self.config.threads.max(1);
+        let start = Instant::now();
 
-            for &child_index in &root.children {
-                let child = &local_mcts.nodes[child_index];
+        if threads > 1 && self.config.parallel_mode == ParallelMode::Root {
+            return self.analyze_parallel(start_state, start, threads);
+        }
 
-                if let Some(m) = child.last_move {
-                    *map.entry(m).or_insert(0) += child.visits;
-                }
-            }
-            map
+        let effort = self.search(start_state.clone(), start, threads);
+
+        let children = self.root_children();
+        let best_move = self.choose(&children);
+
+        SearchReport {
+            principal_variation: self.principal_variation(best_move),
+            nodes: self.nodes.len(),
+            reused_nodes: effort.reused_nodes,
+            reused_visits: effort.reused_visits,
+            distinct_positions: self.distinct_positions(),
+            playouts: effort.playouts,
+            elapsed: start.elapsed(),
+            ..Self::report(&start_state, &children, best_move, self.solved())
+        }
+    }
+
+    fn analyze_parallel(&mut self, start_state: BoardState, start: Instant, threads: usize) -> SearchReport {
+        if self.workers.len() != threads {
+            let seeds: Vec<u64> = (0..threads).map(|_| self.rng.random()).collect();
+
+            self.workers = seeds.into_iter().map(|seed| MCTS::with_config(MctsConfig {
+                threads: 1,
+                seed: Some(seed),
+                ..self.config.clone()
+            })).collect();
+        }
+
+        // each thread grows its own smaller tree, the time budget counting from `start`
+        // for every one of them however rayon schedules them
+        let budget = self.budget;
+        let efforts: Vec<Effort> = self.workers.par_iter_mut().map(|local_mcts| {
+            local_mcts.budget = budget;
+            local_mcts.search(start_state.clone(), start, 1)
         }).collect();
 
-        // merge
-        let mut global_visits = HashMap::new();
-        
-        for r in results {
-            for (m, v) in r {
-                *global_visits.entry(m).or_insert(0) += v;
+        // merge, in an ordered map so that ties are broken the same way on every run
+        let mut merged: BTreeMap<Move, RootChild> = BTreeMap::new();
+
+        for worker in &self.workers {
+            for child in worker.root_children() {
+                let entry = merged.entry(child.mv).or_insert(RootChild {
+                    mv: child.mv,
+                    stats: Stats::default(),
+                    amaf: Stats::default(),
+                    proven: None,
+                });
+
+                entry.stats.visits += child.stats.visits;
+                entry.stats.total_reward += child.stats.total_reward;
+                entry.amaf.visits += child.amaf.visits;
+                entry.amaf.total_reward += child.amaf.total_reward;
+                // a proof from any one tree holds for all of them
+                entry.proven = entry.proven.or(child.proven);
+            }
+        }
+
+        let children: Vec<RootChild> = merged.into_values().collect();
+        let best_move = self.choose(&children);
+
+        // the line as seen by the tree that looked at the best move the most
+        let principal_variation = self.workers.iter()
+            .max_by_key(|w| w.root_children().iter().find(|c| c.mv == best_move).map_or(0, |c| c.stats.visits))
+            .map(|w| w.principal_variation(best_move))
+            .unwrap_or_default();
+
+        let distinct_positions = self.workers.iter().map(|w| w.distinct_positions()).sum();
+
+        SearchReport {
+            principal_variation,
+            nodes: self.workers.iter().map(|w| w.nodes.len()).sum(),
+            reused_nodes: efforts.iter().map(|e| e.reused_nodes).sum(),
+            reused_visits: efforts.iter().map(|e| e.reused_visits).sum(),
+            distinct_positions,
+            playouts: efforts.iter().map(|e| e.playouts).sum(),
+            elapsed: start.elapsed(),
+            ..Self::report(&start_state, &children, best_move, self.workers.iter().find_map(|w| w.solved()))
+        }
+    }
+
+    /// Re-roots the tree at the child reached by `m`, keeping its subtree and its statistics
+    /// for the next search and dropping every other node. Call it for each move played,
+    /// including the engine's own. Without a matching child the tree is thrown away.
+    pub fn advance(&mut self, m: Move) {
+        for worker in &mut self.workers {
+            worker.advance(m);
+        }
+
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let child = self.children(0).find(|&c| self.nodes[c].last_move == Some(m));
+
+        match child {
+            Some(child) => self.prune_to(child),
+            None => self.clear(),
+        }
+    }
+
+    /// Drops the tree and every position the transposition table has on it.
+    fn clear(&mut self) {
+        self.nodes.clear();
+
+        if let Some(table) = &mut self.transpositions {
+            table.retain(|_| false);
+        }
+    }
+
+    /// Compacts the arena down to the subtree under `new_root`, which becomes index 0.
+    fn prune_to(&mut self, new_root: usize) {
+        let mut old: Vec<Option<Node>> = std::mem::take(&mut self.nodes).into_iter().map(Some).collect();
+
+        // breadth-first, so parents always land before their children
+        let mut order = vec![new_root];
+        let mut i = 0;
+        while i < order.len() {
+            let mut child = old[order[i]].as_ref().unwrap().first_child as usize;
+            while child != 0 {
+                order.push(child);
+                child = old[child].as_ref().unwrap().next_sibling as usize;
             }
+            i += 1;
+        }
+
+        let mut remap = vec![usize::MAX; old.len()];
+        for (new_index, &old_index) in order.iter().enumerate() {
+            remap[old_index] = new_index;
         }
 
-        let best = global_visits.into_iter().max_by_key(|&(_, v)| v).unwrap().0;
-        best
+        // the new root's siblings are dropped with the rest
+        let root = old[new_root].as_mut().unwrap();
+        root.parent_index = None;
+        root.next_sibling = 0;
+
+        let link = |index: u32| if index == 0 { 0 } else { remap[index as usize] as u32 };
+
+        for &old_index in &order {
+            let mut node = old[old_index].take().unwrap();
+            node.parent_index = node.parent().map(|p| remap[p] as u32);
+            node.first_child = link(node.first_child);
+            node.next_sibling = link(node.next_sibling);
+            self.nodes.push(node);
+        }
+
+        // positions off the kept subtree can no longer be reached
+        if let Some(table) = &mut self.transpositions {
+            let kept: HashSet<u64> = self.nodes.iter().map(|node| node.hash).collect();
+            table.retain(|hash| kept.contains(&hash));
+        }
     }
 
-    fn best_move(&self, root_index: usize) -> (i32, i32){
-        
-        // WARNING: unwrap
-        let root = self.nodes.get(root_index).unwrap();
+    /// The root moves with their statistics, proofs and AMAF totals.
+    fn root_children(&self) -> Vec<RootChild> {
+        if self.nodes.is_empty() {
+            return vec![];
+        }
+
+        self.children(0).map(|index| {
+            let child = &self.nodes[index];
 
-        if root.children.is_empty() {
+            RootChild {
+                mv: child.last_move.unwrap(),
+                stats: self.stats(index),
+                amaf: child.amaf.load(),
+                proven: child.proven(),
+            }
+        }).collect()
+    }
+
+    /// Plays a proven win when there is one, and otherwise applies the configured
+    /// `MoveSelection` to the moves not proven to lose.
+    fn choose(&self, children: &[RootChild]) -> Move {
+        if children.is_empty() {
             panic!("root node has no children after search()");
-        };
+        }
 
-        let mut best_visits = 0;
-        let mut best_index = root.children[0];
+        if let Some(win) = children.iter().find(|c| c.proven == Some(Proven::Win)) {
+            return win.mv;
+        }
 
-        for child_index in &root.children {
-            let child = self.nodes.get(*child_index).unwrap();
+        // a proven loss is only played when every move loses
+        let mut candidates: Vec<&RootChild> = children.iter().filter(|c| c.proven != Some(Proven::Loss)).collect();
 
-            if child.visits > best_visits {
-                best_visits = child.visits;
-                best_index = *child_index;
+        if candidates.is_empty() {
+            candidates = children.iter().collect();
+        }
+
+        let parent_visits: u32 = candidates.iter().map(|c| c.stats.visits).sum();
+        let c = self.config.exploration_constant;
+
+        let score = |stats: &Stats| {
+            if stats.visits == 0 {
+                return f32::NEG_INFINITY;
+            }
+
+            let n_i = stats.visits as f32;
+            let mean = stats.total_reward / n_i;
+
+            match self.config.move_selection {
+                MoveSelection::MostVisits => n_i,
+                MoveSelection::HighestMean => mean,
+                // the mirror image of the UCT bonus: a lower confidence bound
+                MoveSelection::Secure => mean - c * ((parent_visits as f32).ln() / n_i).sqrt(),
             }
         };
 
-        println!("found best move with {} visits", best_visits);
+        candidates.iter()
+            .max_by(|a, b| score(&a.stats).total_cmp(&score(&b.stats)))
+            .expect("no best move found")
+            .mv
+    }
+
+    /// `best_move` followed by the most visited child at every level below it.
+    fn principal_variation(&self, best_move: Move) -> Vec<Move> {
+        let mut line = vec![];
+
+        if self.nodes.is_empty() {
+            return line;
+        }
+
+        let mut current = self.children(0).find(|&c| self.nodes[c].last_move == Some(best_move));
+
+        while let Some(index) = current {
+            line.push(self.nodes[index].last_move.unwrap());
+
+            current = self.children(index)
+                .filter(|&c| self.nodes[c].stats.load().visits > 0)
+                .max_by_key(|&c| self.nodes[c].stats.load().visits);
+        }
+
+        line
+    }
+
+    /// The proven value of the root for the side to move, the other way round from the root's
+    /// own, which is for the player who moved into it.
+    fn solved(&self) -> Option<Proven> {
+        match self.nodes.first()?.proven()? {
+            Proven::Win => Some(Proven::Loss),
+            Proven::Loss => Some(Proven::Win),
+        }
+    }
+
+    fn distinct_positions(&self) -> Option<usize> {
+        self.transpositions.as_ref().map(TranspositionTable::len)
+    }
+
+    /// The parts of a report that only depend on the root moves; the caller fills in the
+    /// line and the totals.
+    fn report(start_state: &BoardState, children: &[RootChild], best_move: Move, solved: Option<Proven>) -> SearchReport {
+        let mut moves: Vec<MoveReport> = children.iter().map(|c| MoveReport {
+            mv: c.mv,
+            visits: c.stats.visits,
+            mean: if c.stats.visits == 0 { 0.0 } else { c.stats.total_reward / c.stats.visits as f32 },
+            prior: (c.amaf.visits > 0).then(|| c.amaf.total_reward / c.amaf.visits as f32),
+            proven: c.proven,
+        }).collect();
+
+        moves.sort_by_key(|m| Reverse(m.visits));
 
-        let best_move = self.nodes.get(best_index).unwrap().last_move;
+        let best = moves.iter().find(|m| m.mv == best_move).unwrap();
 
-        match best_move {
-            Some(m) => return m,
-            None => panic!("no best move found") 
+        let win_probability = match solved {
+            Some(Proven::Win) => 1.0,
+            Some(Proven::Loss) => 0.0,
+            // a draw scores zero, so it counts as half a win
+            None => (best.mean + 1.0) / 2.0,
         };
 
-        
+        SearchReport {
+            best_move,
+            moves,
+            principal_variation: vec![],
+            win_probability,
+            solved,
+            nodes: 0,
+            reused_nodes: 0,
+            reused_visits: 0,
+            distinct_positions: None,
+            playouts: 0,
+            elapsed: Duration::ZERO,
+            board_size: start_state.board_size,
+            notation: start_state.notation,
+        }
     }
 
-    fn search(&mut self, start_state: BoardState) -> usize {
-      
-        if is_terminal(&start_state) {
-            let root_index = self.nodes.len();
-            self.nodes.push(Node::new(start_state, None, None));
-            return root_index;
+    /// Searches from `start_state` on `threads` threads sharing the tree, growing the retained
+    /// tree when its root is that position and starting a fresh one otherwise. The root always
+    /// sits at index 0. A time budget is measured from `start`.
+    fn search(&mut self, start_state: BoardState, start: Instant, threads: usize) -> Effort {
+        let root_index = 0;
+
+        // the hash covers the stones and the side to move, not the board they are on: every
+        // empty board hashes to zero
+        let root_board = (start_state.board_size, start_state.rules);
+        let reusable = self.root_board == Some(root_board)
+            && self.nodes.first().is_some_and(|root| root.hash == start_state.hash());
+
+        let mut effort = Effort::default();
+
+        if reusable {
+            effort.reused_nodes = self.nodes.len();
+            effort.reused_visits = self.nodes[root_index].stats.load().visits;
+        } else {
+            // the table would otherwise hand the new root the old one's statistics
+            self.clear();
+            self.nodes.push(Node::new(&start_state, None, None));
+            self.root_board = Some(root_board);
         }
 
-        let root_index = self.nodes.len();
-        self.nodes.push(Node::new(start_state, None, None));
+        if self.nodes[root_index].is_terminal {
+            return effort;
+        }
 
-        for _ in 0..self.max_iter {
-            let node_index = self.select(root_index);
+        self.shared = threads > 1;
 
-            if self.nodes[node_index].is_terminal {
-                let reward = self.simulate(node_index);
-                self.back_propagation(reward, node_index);
-                continue;
-            }
+        let budget = self.budget;
+        let max_nodes = self.config.max_nodes;
+        let seeds: Vec<u64> = (0..threads).map(|_| self.rng.random()).collect();
+        let policy = self.config.playout.policy();
+        let iterations = AtomicU32::new(0);
+        let tree = RwLock::new(&mut *self);
+
+        let worker = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            // every thread has one working board: selection and playouts make moves on it
+            // and every iteration ends by taking them back to the root position
+            let mut board = start_state.clone();
+            let root_ply = board.ply();
+            let root_stones = [*board.stones(Player::P1), *board.stones(Player::P2)];
+
+            // a root solved by an earlier search needs no more iterations
+            while tree.read().unwrap().nodes[root_index].proven().is_none() {
+                let done = iterations.fetch_add(1, Ordering::Relaxed) + 1;
+
+                Self::iterate(&tree, &mut board, &*policy, &mut rng, root_index, &root_stones);
+                board.undo_to(root_ply);
 
-            let expanded_index = self.expand(node_index);
-            let reward = self.simulate(expanded_index);
+                if budget.exhausted(done, start.elapsed()) { break; }
+                if max_nodes.is_some_and(|max| tree.read().unwrap().nodes.len() >= max) { break; }
+            }
+        };
 
-            self.back_propagation(reward, expanded_index);
+        if threads == 1 {
+            worker(seeds[0]);
+        } else {
+            thread::scope(|scope| {
+                for &seed in &seeds {
+                    scope.spawn(move || worker(seed));
+                }
+            });
         }
 
-        println!("looked through {} moves", self.nodes.len());
+        effort.playouts = iterations.into_inner();
+        effort
+    }
+
+    /// One select, expand, simulate and back-propagate round, leaving its moves on `board`.
+    /// Only expansion takes the write lock; the playout runs without holding the lock at all.
+    fn iterate(tree: &RwLock<&mut MCTS>, board: &mut BoardState, policy: &dyn PlayoutPolicy, rng: &mut StdRng, root_index: usize, root_stones: &[Bitboard; 2]) {
+        let (mut leaf_index, proven, is_terminal) = {
+            let mcts = tree.read().unwrap();
+            let leaf_index = mcts.select(root_index, board);
+            let leaf = &mcts.nodes[leaf_index];
+
+            (leaf_index, leaf.proven(), leaf.is_terminal)
+        };
+
+        // a proven leaf has an exact value, there is nothing to sample
+        let reward = match proven {
+            Some(proven) => proven.reward(),
+            None => {
+                if !is_terminal {
+                    let mut mcts = tree.write().unwrap();
+                    leaf_index = mcts.expand(leaf_index, board, rng);
+                    board.apply_move(mcts.nodes[leaf_index].last_move.unwrap()).unwrap();
+                }
+
+                Self::simulate(board, policy, rng)
+            }
+        };
 
-        return root_index
-        
+        let mcts = tree.read().unwrap();
+
+        mcts.back_propagation(reward, leaf_index);
+        mcts.propagate_proof(leaf_index);
+        if mcts.config.rave.is_some() {
+            mcts.update_amaf(reward, leaf_index, board, root_stones);
+        }
     }
 
-    fn select(&self, start_index: usize) -> usize {
+    /// Walks down to a leaf or a proven node, playing the chosen moves on `board` along the way
+    /// and, on a shared tree, putting a virtual loss on each node picked.
+    fn select(&self, start_index: usize, board: &mut BoardState) -> usize {
         let node = &self.nodes[start_index];
 
-        if node.children.is_empty() {
+        if node.proven().is_some() || self.wants_child(start_index) {
             return start_index;
         }
 
-        // WARNING: (MILD) index 0 is unsafe but with NEG_INFINITY UCT the first child picked should always overwrite it
-        let (mut best_uct,mut best_index): (f32, usize) = (f32::NEG_INFINITY, 0);
+        let (mut best_uct, mut best_index): (f32, Option<usize>) = (f32::NEG_INFINITY, None);
+        let parent_visits = self.stats(start_index).visits;
 
-        for index in &node.children {
-            if let Some(child_node) = self.nodes.get(*index) {
-                let uct = self.calculate_uct(child_node, node.visits);
-                
-                if (uct > best_uct) {
-                    best_uct = uct;
-                    best_index = *index;
-                }
-            };   
+        for index in self.children(start_index) {
+            let child = &self.nodes[index];
+
+            let uct = match child.proven() {
+                Some(Proven::Win) => f32::INFINITY,
+                Some(Proven::Loss) => f32::NEG_INFINITY,
+                None => self.calculate_uct(self.stats(index), child.amaf.load(), parent_visits),
+            };
+
+            if uct > best_uct {
+                best_uct = uct;
+                best_index = Some(index);
+            }
         };
 
-        return self.select(best_index);
-    }
+        // every child turned out a proven loss while this thread was looking: with no untried
+        // moves left the node is a win the proof has not reached yet, and otherwise it wants
+        // a child after all
+        let Some(best_index) = best_index else {
+            if node.untried.is_empty() {
+                node.prove(Proven::Win);
+            }
+            return start_index;
+        };
 
-    fn expand(&mut self, node_index: usize) -> usize {
+        if self.shared {
+            self.nodes[best_index].virtual_loss.fetch_add(1, Ordering::Relaxed);
+        }
+        board.apply_move(self.nodes[best_index].last_move.unwrap()).unwrap();
 
+        self.select(best_index, board)
+    }
 
-        let moves = {
-            let node = &mut self.nodes[node_index];
-            node.state.legal_moves()
-        };
+    /// True when selection should stop at `node` to give it another child: a leaf, or a
+    /// node with untried moves and, with progressive widening, room for another child or
+    /// nothing but proven losses to choose from.
+    fn wants_child(&self, index: usize) -> bool {
+        let node = &self.nodes[index];
 
-        if moves.is_empty() {
-            panic!("no legal moves available")
+        if node.child_count == 0 {
+            return true;
         }
 
-        // Create ALL children at once
-        for &move_coords in &moves {
-            let mut new_state = self.nodes[node_index].state.clone();
-            new_state.apply_move(move_coords).unwrap();
-            
-            let new_node = Node::new(new_state, Some(node_index), Some(move_coords));
-            let new_index = self.nodes.len();
-            
-            self.nodes.push(new_node);
-            self.nodes[node_index].children.push(new_index);
+        if node.untried.is_empty() {
+            return false;
         }
 
-        let children = &self.nodes[node_index].children;
-        let random_idx = self.get_random_move_index(children.len());
-        children[random_idx]
-       
+        match self.config.widening {
+            Some(widening) => (node.child_count as usize) < widening.max_children(node.stats.load().visits)
+                || self.children(index).all(|c| self.nodes[c].proven() == Some(Proven::Loss)),
+            None => true,
+        }
     }
 
-    fn simulate(&self, start_index: usize) -> f32  {
-        if let Some(node) = self.nodes.get(start_index) {
+    /// Makes one untried move of `node_index` into a child and returns it, listing the legal
+    /// moves first when the node has never been expanded, or only the decisive ones if there
+    /// are any. When another thread has taken the last untried move, or the widening limit,
+    /// in the meantime, it picks an existing child at random instead. `board` is the position
+    /// at `node_index` and is left there.
+    fn expand(&mut self, node_index: usize, board: &mut BoardState, rng: &mut StdRng) -> usize {
+        let geometry = board.geometry();
+
+        if self.nodes[node_index].child_count == 0 && self.nodes[node_index].untried.is_empty() {
+            let moves = board.legal_moves();
 
-            // in case expanded_node is already terminal
-            if node.is_terminal {
-                let winner = node.state.get_winner();
-                return match winner {
-                    Some(p) if p == node.player_to_move => -1.0,
-                    Some(_) => 1.0,
-                    None => 0.0,
-                };
+            if moves.is_empty() {
+                panic!("no legal moves available")
             }
 
+            // with a win on the board, or one to stop, every other move loses
+            let decisive = playout::decisive_cells(board);
 
-            let mut board = node.state.clone();
-            
-            let mut n_moves = 0;
+            self.nodes[node_index].untried = if decisive.is_empty() {
+                moves.iter().map(|m| match *m {
+                    Move::Place(q, r) => geometry.cell(q, r).unwrap() as u16,
+                    Move::Swap => SWAP,
+                }).collect()
+            } else {
+                decisive.iter().map(|cell| cell as u16).collect()
+            };
+        }
 
-            while !board.is_terminal() {
-                let moves = board.legal_moves();
-                let r_index = self.get_random_move_index(moves.len());
-                let r_move = moves[r_index];
-                board.apply_move(r_move).unwrap();
+        let child_index = if self.wants_child(node_index) {
+            let untried = &mut self.nodes[node_index].untried;
+            let cell = untried.swap_remove(rng.random_range(0..untried.len()));
+            if untried.is_empty() {
+                // nothing more to hold on to for a fully expanded node
+                *untried = vec![];
+            }
 
-                n_moves += 1;
+            let m = match cell {
+                SWAP => Move::Swap,
+                cell => {
+                    let (q, r) = geometry.coords[cell as usize];
+                    Move::Place(q, r)
+                }
             };
 
-            let winner = board.get_winner();
+            board.apply_move(m).unwrap();
+            let mut child = Node::new(board, Some(node_index), Some(m));
+            board.undo_move().unwrap();
 
-            // since board contains the turn after the node was expanded.
-            let last_player = match node.player_to_move {
-                Player::P1 => Player::P2,
-                Player::P2 => Player::P1,
-            };
+            let child_index = self.nodes.len();
+            let node = &mut self.nodes[node_index];
+            child.next_sibling = node.first_child;
+            node.first_child = child_index as u32;
+            node.child_count += 1;
+
+            self.nodes.push(child);
+            child_index
+        } else {
+            let nth = rng.random_range(0..self.nodes[node_index].child_count as usize);
+            self.children(node_index).nth(nth).unwrap()
+        };
 
-            return match winner {
-                Some(p) if p == last_player => 1.0,
-                Some(_) => -1.0,
-                None => 0.0,
+        if self.shared {
+            self.nodes[child_index].virtual_loss.fetch_add(1, Ordering::Relaxed);
+        }
+        child_index
+    }
+
+    /// Plays a game out on `board`, with `policy` picking every move that is not decisive, and
+    /// scores it for the player who made the move leading to the starting position. The moves
+    /// are left on the board for the caller to take back.
+    fn simulate(board: &mut BoardState, policy: &dyn PlayoutPolicy, rng: &mut StdRng) -> f32  {
+        let last_player = board.turn.opponent();
+
+        // stops on a full board as well as on a win
+        while !board.is_terminal() {
+            let m = match playout::decisive_move(board, rng) {
+                Some(m) => m,
+                None => policy.choose(board, rng),
             };
-        }   
+            board.apply_move(m).unwrap();
+        };
 
-        else {
-            panic!("no node to simulate.");
+        match board.result() {
+            GameResult::Win(p) if p == last_player => 1.0,
+            GameResult::Win(_) => -1.0,
+            GameResult::Draw | GameResult::Ongoing => 0.0,
         }
     }
 
-    fn back_propagation(&mut self, mut reward: f32, expanded_index: usize) {
+    /// Adds the result to every node on the path and lifts the virtual losses `select` and
+    /// `expand` put there.
+    fn back_propagation(&self, mut reward: f32, expanded_index: usize) {
         let mut current_index = Some(expanded_index);
 
         while let Some(index) = current_index {
-            let node = &mut self.nodes[index];
-            node.visits += 1;
-            node.total_reward += reward;
+            let node = &self.nodes[index];
+            node.stats.add(reward);
+
+            // the root is never picked, so it carries no virtual loss
+            if self.shared && node.parent_index.is_some() {
+                node.virtual_loss.fetch_sub(1, Ordering::Relaxed);
+            }
+
+            if let Some(table) = &self.transpositions {
+                table.add(node.hash, reward);
+            }
 
             reward = -reward;
 
-            current_index = node.parent_index;
+            current_index = node.parent();
         }
     }
 
-    fn calculate_uct(&self, node: &Node, parent_visits: u32) -> f32 {
-        if node.visits == 0 {
-            return f32::INFINITY;
+    /// Carries a proof at `index` up the tree: a parent with a winning move is lost for the
+    /// player who moved into it, and one whose every move loses is won for that player.
+    fn propagate_proof(&self, index: usize) {
+        let mut current_index = index;
+
+        while let Some(proven) = self.nodes[current_index].proven()
+            && let Some(parent_index) = self.nodes[current_index].parent()
+            && self.nodes[parent_index].proven().is_none()
+        {
+            let parent = &self.nodes[parent_index];
+
+            let parent_proven = match proven {
+                Proven::Win => Proven::Loss,
+                // every move has to have been tried before they can all be said to lose
+                Proven::Loss if parent.untried.is_empty() && self.children(parent_index).all(|c| self.nodes[c].proven() == Some(Proven::Loss)) => Proven::Win,
+                Proven::Loss => return,
+            };
+
+            parent.prove(parent_proven);
+            current_index = parent_index;
         }
+    }
+
+    /// Credits the playout to every child along the path whose move the player choosing
+    /// there went on to make anyway, later in the tree or in the playout. `board` is the
+    /// final position and `root_stones` the stones at the root.
+    fn update_amaf(&self, mut reward: f32, leaf_index: usize, board: &BoardState, root_stones: &[Bitboard; 2]) {
+        let geometry = board.geometry();
+        let played = [Player::P1, Player::P2].map(|p| board.stones(p).difference(&root_stones[p.index()]));
+
+        let mut current_index = Some(leaf_index);
+
+        while let Some(index) = current_index {
+            let node = &self.nodes[index];
+            let mover = node.player_to_move.index();
+
+            for child_index in self.children(index) {
+                let child = &self.nodes[child_index];
+
+                if let Some(Move::Place(q, r)) = child.last_move
+                    && let Some(cell) = geometry.cell(q, r)
+                    && played[mover].contains(cell)
+                {
+                    // `reward` is for the player who moved into `index`, the children are the other side's moves
+                    child.amaf.add(-reward);
+                }
+            }
+
+            reward = -reward;
+
+            current_index = node.parent();
+        }
+    }
+
+    fn children(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let first = self.nodes[index].first_child as usize;
+
+        iter::successors((first != 0).then_some(first), |&child| {
+            let next = self.nodes[child].next_sibling as usize;
+            (next != 0).then_some(next)
+        })
+    }
+
+    /// Statistics selection should use for a node: the shared entry for its position
+    /// when the transposition table is on, its own counters otherwise, with any virtual
+    /// loss counted in.
+    fn stats(&self, index: usize) -> Stats {
+        let node = &self.nodes[index];
+
+        let stats = match &self.transpositions {
+            Some(table) => table.get(node.hash),
+            None => node.stats.load(),
+        };
+
+        let virtual_loss = node.virtual_loss.load(Ordering::Relaxed);
+
+        Stats {
+            visits: stats.visits + virtual_loss,
+            total_reward: stats.total_reward - virtual_loss as f32,
+        }
+    }
+
+    fn calculate_uct(&self, stats: Stats, amaf: Stats, parent_visits: u32) -> f32 {
+        let w_i = stats.total_reward;
+        let n_i = stats.visits as f32;
+        let c = self.config.exploration_constant;
+
+        let value = match self.config.rave {
+            // with AMAF data an unvisited move is ranked on that alone, beta is 1 at zero visits
+            Some(schedule) if amaf.visits > 0 => {
+                let beta = schedule.beta(stats.visits);
+                let own = if stats.visits == 0 { 0.0 } else { w_i / n_i };
+
+                (1.0 - beta) * own + beta * (amaf.total_reward / amaf.visits as f32)
+            }
+            _ if stats.visits == 0 => return f32::INFINITY,
+            _ => w_i / n_i,
+        };
+
         if parent_visits == 0 {
-            return 0.0;
+            return value;
+        }
+
+        value + c * (((parent_visits as f32).ln() / n_i.max(1.0)).sqrt())
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{ParallelMode, Proven, MCTS};
+    use crate::{board::{BoardState, Move, Player}, config::MctsConfig, report::SearchReport};
+
+    fn engine(iterations: u32) -> MCTS {
+        MCTS::with_config(MctsConfig::default().iterations(iterations).seed(1).verbose(false))
+    }
+
+    /// A size 4 board with `stones` placed for each player in turn, `turn` to move.
+    fn position(stones: [&[(i32, i32)]; 2], turn: Player) -> BoardState {
+        let mut board = BoardState::new(4);
+
+        for (player, cells) in [Player::P1, Player::P2].into_iter().zip(stones) {
+            for &(q, r) in cells {
+                board.turn = player;
+                board.apply_move(Move::Place(q, r)).unwrap();
+            }
+        }
+
+        board.turn = turn;
+        board
+    }
+
+    #[test]
+    fn a_win_in_one_is_proven_and_ends_the_search() {
+        // a bridge between the corners (-3, 0) and (3, 0) lacking only the centre
+        let board = position([&[(-3, 0), (-2, 0), (-1, 0), (1, 0), (2, 0), (3, 0)], &[]], Player::P1);
+        let report = engine(1000).analyze(board);
+
+        assert_eq!(report.solved, Some(Proven::Win));
+        assert_eq!(report.best_move, Move::Place(0, 0));
+        assert!(report.playouts < 10, "{} playouts", report.playouts);
+    }
+
+    #[test]
+    fn two_winning_cells_for_the_opponent_are_a_proven_loss() {
+        // the same bridge lacking the centre, and one along the edge lacking (-3, 2)
+        let board = position([&[], &[(-3, 0), (-2, 0), (-1, 0), (1, 0), (2, 0), (3, 0), (-3, 1), (-3, 3)]], Player::P1);
+        let report = engine(1000).analyze(board);
+
+        assert_eq!(report.solved, Some(Proven::Loss));
+        assert!(report.moves.iter().all(|m| m.proven == Some(Proven::Loss)));
+    }
+
+    #[test]
+    fn a_node_whose_children_all_lose_is_a_win_when_selected() {
+        // as when other threads proved the children but not yet the node
+        let mut mcts = engine(200);
+        let board = BoardState::new(3);
+        mcts.analyze(board.clone());
+
+        assert!(mcts.nodes[0].untried.is_empty() && mcts.nodes[0].proven().is_none());
+        for child in mcts.children(0).collect::<Vec<_>>() {
+            mcts.nodes[child].prove(Proven::Loss);
         }
 
-        let w_i = node.total_reward;
-        let n_i = node.visits as f32;
-        let c = self.exploration_constant;
+        assert_eq!(mcts.select(0, &mut board.clone()), 0);
+        assert_eq!(mcts.nodes[0].proven(), Some(Proven::Win));
+    }
+
+    #[test]
+    fn empty_boards_of_another_size_get_a_fresh_tree() {
+        let mut mcts = engine(200);
+        mcts.analyze(BoardState::new(5));
+
+        // both empty boards hash to zero
+        let small = BoardState::new(3);
+        let report = mcts.analyze(small.clone());
+
+        assert_eq!((report.reused_nodes, report.reused_visits), (0, 0));
+        assert!(small.legal_moves().contains(&report.best_move));
+    }
+
+    #[test]
+    fn the_transposition_table_only_holds_positions_of_the_tree() {
+        let mut mcts = MCTS::with_config(MctsConfig::default().iterations(300).seed(1).transpositions(true).verbose(false));
+        mcts.analyze(BoardState::new(5));
+
+        // a fresh root must not read the statistics of the empty board searched before
+        let mut board = BoardState::new(3);
+        let report = mcts.analyze(board.clone());
+        assert!(report.distinct_positions.unwrap() <= report.nodes);
+
+        board.apply_move(report.best_move).unwrap();
+        mcts.advance(report.best_move);
+        let report = mcts.analyze(board);
+        assert!(report.reused_nodes > 0);
+        assert!(report.distinct_positions.unwrap() <= report.nodes);
+    }
+
+    #[test]
+    fn the_same_seed_and_iterations_replay_the_same_search() {
+        for threads in [1, 3] {
+            let search = || {
+                let config = MctsConfig::default().seed(7).iterations(300).threads(threads, ParallelMode::Root);
+                MCTS::with_config(config.verbose(false)).analyze(BoardState::new(4))
+            };
+            let (first, second) = (search(), search());
+            let visits = |report: &SearchReport| report.moves.iter().map(|m| (m.mv, m.visits)).collect::<Vec<_>>();
 
-        (w_i / n_i) + c * (((parent_visits as f32).ln() / n_i).sqrt())
+            assert_eq!(first.best_move, second.best_move);
+            assert_eq!(first.nodes, second.nodes);
+            assert_eq!(visits(&first), visits(&second));
         }
+    }
+
+    #[test]
+    fn threads_sharing_a_tree_back_up_every_iteration() {
+        for transpositions in [false, true] {
+            let config = MctsConfig::default().iterations(400).threads(4, ParallelMode::Tree).transpositions(transpositions);
+            let mut mcts = MCTS::with_config(config.verbose(false));
+            let board = BoardState::new(5);
+
+            let report = mcts.analyze(board.clone());
+            assert!(report.playouts >= 400);
+            assert_eq!(report.moves.iter().map(|m| m.visits).sum::<u32>(), report.playouts);
+
+            // every virtual loss has been lifted again
+            assert_eq!(mcts.nodes.iter().map(|node| node.virtual_loss.load(Ordering::Relaxed)).sum::<u32>(), 0);
+            assert_eq!(mcts.analyze(board).reused_visits, report.playouts);
+        }
+    }
+
+    #[test]
+    fn a_retained_tree_keeps_its_root_visits() {
+        let mut mcts = engine(200);
+        let board = BoardState::new(4);
+        let first = mcts.analyze(board.clone());
+        let second = mcts.analyze(board);
+
+        assert_eq!(second.reused_nodes, first.nodes);
+        assert_eq!(second.reused_visits, 200);
+    }
 
+    #[test]
+    fn widening_opens_children_as_the_visits_grow() {
+        for (coefficient, exponent) in [(1.0, 0.5), (2.0, 0.4)] {
+            let config = MctsConfig::default().iterations(500).seed(1).widening(coefficient, exponent);
+            let mut mcts = MCTS::with_config(config.verbose(false));
+            mcts.analyze(BoardState::new(5));
+
+            let (children, visits) = (mcts.nodes[0].child_count as usize, mcts.stats(0).visits);
+            let limit = (coefficient * (visits as f32).powf(exponent)).ceil() as usize;
+
+            assert!(children <= limit, "{} children for {} visits", children, visits);
+            assert!(children > 1 && !mcts.nodes[0].untried.is_empty());
+        }
+    }
+
+    #[test]
+    fn widening_opens_another_child_when_the_opened_ones_all_lose() {
+        let mut mcts = MCTS::with_config(MctsConfig::default().iterations(4).seed(1).widening(1.0, 0.5).verbose(false));
+        let mut board = BoardState::new(5);
+        mcts.analyze(board.clone());
+
+        let children: Vec<usize> = mcts.children(0).collect();
+        assert!(!mcts.wants_child(0), "{} children for {} visits", children.len(), mcts.stats(0).visits);
+
+        for &child in &children {
+            mcts.nodes[child].prove(Proven::Loss);
+        }
 
-    fn get_random_move_index(&self, max: usize) -> usize {
-        rand::rng().random_range(0..max)
+        assert_eq!(mcts.select(0, &mut board), 0);
+        let child = mcts.expand(0, &mut board, &mut StdRng::seed_from_u64(1));
+        assert!(!children.contains(&child));
+        assert_eq!(mcts.nodes[0].child_count as usize, children.len() + 1);
     }
 }
@@ -0,0 +1,139 @@
+use rand::{rngs::StdRng, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{bitboard::{Bitboard, NO_CELL}, board::{BoardState, Move}};
+
+/// Chance that the heavy policy answers next to the last stone when nothing more urgent
+/// turned up, instead of anywhere on the board.
+const LOCAL_PROBABILITY: f64 = 0.2;
+
+/// How the moves of a playout are picked, from the position left after selection and
+/// expansion until the game is over.
+pub trait PlayoutPolicy: Send + Sync {
+    /// Next move for the side to move on `board`, which nobody has won yet and which has an
+    /// empty cell left. Only asked when there is no `decisive_move` to play. The board may be
+    /// used for look-ahead but has to be left as it was.
+    fn choose(&self, board: &mut BoardState, rng: &mut StdRng) -> Move;
+}
+
+/// Which `PlayoutPolicy` a search uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Playout {
+    #[default]
+    Uniform,
+    Heavy,
+}
+
+impl Playout {
+    pub fn policy(self) -> Box<dyn PlayoutPolicy> {
+        match self {
+            Playout::Uniform => Box::new(Uniform),
+            Playout::Heavy => Box::new(Heavy),
+        }
+    }
+}
+
+/// Every legal move equally likely.
+pub struct Uniform;
+
+impl PlayoutPolicy for Uniform {
+    fn choose(&self, board: &mut BoardState, rng: &mut StdRng) -> Move {
+        let moves = board.legal_moves();
+        moves[rng.random_range(0..moves.len())]
+    }
+}
+
+/// Cells the side to move has to play at: its own winning cells when it has any, and
+/// otherwise the cells where the opponent would win next, which need blocking. Empty when
+/// nothing is that urgent.
+pub fn decisive_cells(board: &BoardState) -> Bitboard {
+    let wins = board.winning_cells(board.turn);
+
+    if wins.is_empty() {
+        board.winning_cells(board.turn.opponent())
+    } else {
+        wins
+    }
+}
+
+/// One of the `decisive_cells` at random, if there are any.
+pub fn decisive_move(board: &BoardState, rng: &mut StdRng) -> Option<Move> {
+    let cells = decisive_cells(board);
+
+    if cells.is_empty() {
+        return None;
+    }
+
+    let cell = cells.iter().nth(rng.random_range(0..cells.count() as usize)).unwrap();
+    let (q, r) = board.geometry().coords[cell];
+
+    Some(Move::Place(q, r))
+}
+
+/// Havannah patterns around the last stone, tried after the decisive moves: saving a bridge
+/// the opponent just stepped into and, some of the time, any move next to the last stone.
+/// Uniform otherwise.
+pub struct Heavy;
+
+impl PlayoutPolicy for Heavy {
+    fn choose(&self, board: &mut BoardState, rng: &mut StdRng) -> Move {
+        let geometry = board.geometry();
+        let me = board.turn;
+        let empty = geometry.all.difference(&board.occupied());
+        let place = |cell: usize| {
+            let (q, r) = geometry.coords[cell];
+            Move::Place(q, r)
+        };
+
+        // the last stone was the opponent's
+        let Some(last) = board.recent_cell(0) else {
+            return Uniform.choose(board, rng);
+        };
+
+        // two of our stones two steps apart around `last` were linked through it and the
+        // cell between them; with `last` taken, that cell is the only link left
+        let ring = geometry.neighbours[last];
+        let own = board.stones(me);
+        let saves: Vec<usize> = (0..6)
+            .filter(|&i| {
+                let (a, b, between) = (ring[i], ring[(i + 2) % 6], ring[(i + 1) % 6]);
+                a != NO_CELL && b != NO_CELL && between != NO_CELL
+                    && own.contains(a as usize) && own.contains(b as usize) && empty.contains(between as usize)
+            })
+            .map(|i| ring[(i + 1) % 6] as usize)
+            .collect();
+
+        if !saves.is_empty() {
+            return place(saves[rng.random_range(0..saves.len())]);
+        }
+
+        let local: Vec<usize> = geometry.neighbours_of(last).filter(|&n| empty.contains(n)).collect();
+        if !local.is_empty() && rng.random_bool(LOCAL_PROBABILITY) {
+            return place(local[rng.random_range(0..local.len())]);
+        }
+
+        Uniform.choose(board, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{Heavy, PlayoutPolicy};
+    use crate::board::{BoardState, Move};
+
+    #[test]
+    fn heavy_saves_a_bridge_the_opponent_stepped_into() {
+        // (1, 0) and (0, -1) are linked through (0, 0) and (1, -1), and the second player takes (0, 0)
+        let mut board = BoardState::new(5);
+        for (q, r) in [(1, 0), (-3, 3), (0, -1), (0, 0)] {
+            board.apply_move(Move::Place(q, r)).unwrap();
+        }
+
+        for seed in 0..20 {
+            assert_eq!(Heavy.choose(&mut board, &mut StdRng::seed_from_u64(seed)), Move::Place(1, -1));
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::{fmt, fs, iter::Peekable, path::Path, str::{Chars, FromStr}, time::Duration};
+
+use crate::{bitboard::MAX_BOARD_SIZE, board::{BoardState, GameResult, Move, Player, Rules}, config::MctsConfig, report::SearchReport};
+
+/// One side of a recorded game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerInfo {
+    pub name: String,
+    /// settings of the engine that played this side, none for a person
+    pub engine: Option<MctsConfig>,
+}
+
+impl PlayerInfo {
+    pub fn human(name: &str) -> Self {
+        Self { name: name.to_string(), engine: None }
+    }
+
+    pub fn engine(name: &str, config: &MctsConfig) -> Self {
+        Self { name: name.to_string(), engine: Some(config.clone()) }
+    }
+}
+
+/// What the search behind an engine move looked at, as far as it is known. Games from
+/// older logs only kept some of it.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MoveStats {
+    pub playouts: Option<u32>,
+    pub nodes: Option<usize>,
+    /// the mover's own estimate of its chances after the search
+    pub win_probability: Option<f32>,
+}
+
+impl From<&SearchReport> for MoveStats {
+    fn from(report: &SearchReport) -> Self {
+        Self {
+            playouts: Some(report.playouts),
+            nodes: Some(report.nodes),
+            win_probability: Some(report.win_probability),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedMove {
+    pub m: Move,
+    /// how long the side took over the move
+    pub time: Option<Duration>,
+    /// for engine moves
+    pub stats: Option<MoveStats>,
+}
+
+/// A game as it was played, saved as an SGF-style text file.
+///
+/// The root node holds the board size `SZ`, the rules `RU`, the player names `PB` and `PW`
+/// with their engine settings as JSON in `EB` and `EW`, and once the game is over the result
+/// `RE` with the kind `WK` and cells `WC` of the winning structure. Every move follows in a
+/// node of its own, `B` for the first player and `W` for the second, in a1 notation, with
+/// the time taken `MT` in seconds and the search's playouts `PO`, nodes `NO` and win
+/// probability `WP`. The reader replays the moves and works the result out again, so `RE`,
+/// `WK` and `WC` are there for people and other programs.
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    pub board_size: i8,
+    pub rules: Rules,
+    /// indexed by `Player::index`
+    pub players: [PlayerInfo; 2],
+    pub moves: Vec<RecordedMove>,
+}
+
+impl GameRecord {
+    /// Starts a record from `board`, taking over the moves already played on it without
+    /// times or statistics.
+    pub fn new(board: &BoardState, players: [PlayerInfo; 2]) -> Self {
+        Self {
+            board_size: board.board_size,
+            rules: board.rules,
+            players,
+            moves: board.moves().map(|m| RecordedMove { m, time: None, stats: None }).collect(),
+        }
+    }
+
+    pub fn push(&mut self, m: Move, time: Duration, stats: Option<MoveStats>) {
+        self.moves.push(RecordedMove { m, time: Some(time), stats });
+    }
+
+    /// The position after the first `ply` moves, or after all of them when there are fewer.
+    pub fn board_at(&self, ply: usize) -> BoardState {
+        let mut board = BoardState::with_rules(self.board_size, self.rules);
+
+        for recorded in self.moves.iter().take(ply) {
+            board.apply_move(recorded.m).expect("recorded moves are legal");
+        }
+
+        board
+    }
+
+    pub fn final_board(&self) -> BoardState {
+        self.board_at(self.moves.len())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Reads a record written by `Display`, checking every move against the rules. Moves may
+    /// be in either notation; properties the reader does not know are skipped.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut nodes = parse_nodes(text)?.into_iter();
+        let root = nodes.next().ok_or("the record is empty")?;
+
+        if let Some(game) = value(&root, "GM") && !game.eq_ignore_ascii_case("havannah") {
+            return Err(format!("not a Havannah record: GM[{}]", game));
+        }
+
+        let board_size = number::<i8>(&root, "SZ")?
+            .filter(|size| (2..=MAX_BOARD_SIZE).contains(size))
+            .ok_or(format!("expected a board size SZ between 2 and {}", MAX_BOARD_SIZE))?;
+        let rules = Rules { swap: value(&root, "RU").is_some_and(|rules| rules.eq_ignore_ascii_case("swap")) };
+
+        let player = |name: &str, engine: &str, default: &str| -> Result<PlayerInfo, String> {
+            Ok(PlayerInfo {
+                name: value(&root, name).unwrap_or(default).to_string(),
+                engine: value(&root, engine).map(MctsConfig::from_json).transpose()?,
+            })
+        };
+        let players = [player("PB", "EB", "Black")?, player("PW", "EW", "White")?];
+
+        let mut board = BoardState::with_rules(board_size, rules);
+        let mut moves = vec![];
+
+        for (i, node) in nodes.enumerate() {
+            let context = |e: &str| format!("move {}: {}", i + 1, e);
+
+            let (color, m) = match (value(&node, "B"), value(&node, "W")) {
+                (Some(m), None) => (Player::P1, m),
+                (None, Some(m)) => (Player::P2, m),
+                _ => return Err(context("expected one B or W move")),
+            };
+
+            if color != board.turn {
+                return Err(context("played out of turn"));
+            }
+            if board.is_terminal() {
+                return Err(context("the game is already over"));
+            }
+
+            let m = board.parse_move(m).and_then(|m| board.apply_move(m)).map_err(context)?;
+
+            let time = number::<f64>(&node, "MT").map_err(|e| context(&e))?
+                .map(|seconds| Duration::try_from_secs_f64(seconds.max(0.0)).map_err(|_| context("MT is out of range")))
+                .transpose()?;
+            let stats = MoveStats {
+                playouts: number(&node, "PO").map_err(|e| context(&e))?,
+                nodes: number(&node, "NO").map_err(|e| context(&e))?,
+                win_probability: number(&node, "WP").map_err(|e| context(&e))?,
+            };
+            let stats = (stats != MoveStats::default()).then_some(stats);
+
+            moves.push(RecordedMove { m, time, stats });
+        }
+
+        Ok(Self { board_size, rules, players, moves })
+    }
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(;FF[4]GM[havannah]AP[{}:{}]", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))?;
+        writeln!(f, "SZ[{}]RU[{}]", self.board_size, if self.rules.swap { "swap" } else { "none" })?;
+
+        for (player, (name, engine)) in self.players.iter().zip([("PB", "EB"), ("PW", "EW")]) {
+            write!(f, "{}[{}]", name, escape(&player.name))?;
+            if let Some(config) = &player.engine {
+                write!(f, "{}[{}]", engine, escape(&serde_json::to_string(config).unwrap()))?;
+            }
+            writeln!(f)?;
+        }
+
+        let board = self.final_board();
+        let result = match board.result() {
+            GameResult::Win(Player::P1) => Some("B+"),
+            GameResult::Win(Player::P2) => Some("W+"),
+            GameResult::Draw => Some("0"),
+            GameResult::Ongoing => None,
+        };
+
+        if let Some(result) = result {
+            write!(f, "RE[{}]", result)?;
+            if let Some(win) = board.win_info() {
+                write!(f, "WK[{}]WC", win.kind)?;
+                for (q, r) in win.cells {
+                    write!(f, "[{}]", Move::Place(q, r).to_notation(self.board_size))?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        for (i, recorded) in self.moves.iter().enumerate() {
+            let color = if i % 2 == 0 { "B" } else { "W" };
+            write!(f, ";{}[{}]", color, recorded.m.to_notation(self.board_size))?;
+
+            if let Some(time) = recorded.time {
+                write!(f, "MT[{:.3}]", time.as_secs_f64())?;
+            }
+            if let Some(stats) = recorded.stats {
+                if let Some(playouts) = stats.playouts {
+                    write!(f, "PO[{}]", playouts)?;
+                }
+                if let Some(nodes) = stats.nodes {
+                    write!(f, "NO[{}]", nodes)?;
+                }
+                if let Some(win_probability) = stats.win_probability {
+                    write!(f, "WP[{:.3}]", win_probability)?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, ")")
+    }
+}
+
+/// Properties of one node, each with its values.
+type Node = Vec<(String, Vec<String>)>;
+
+/// First value of the property `id`.
+fn value<'a>(node: &'a Node, id: &str) -> Option<&'a str> {
+    node.iter().find(|(property, _)| property == id).map(|(_, values)| values[0].as_str())
+}
+
+fn number<T: FromStr>(node: &Node, id: &str) -> Result<Option<T>, String> {
+    value(node, id)
+        .map(|v| v.trim().parse().map_err(|_| format!("{}[{}] is not a number", id, v)))
+        .transpose()
+}
+
+/// Splits a record into the nodes of its single line of play.
+fn parse_nodes(text: &str) -> Result<Vec<Node>, String> {
+    let mut chars = text.chars().peekable();
+    let mut nodes: Vec<Node> = vec![];
+
+    skip_whitespace(&mut chars);
+    if chars.next() != Some('(') {
+        return Err("a record starts with `(`".to_string());
+    }
+
+    loop {
+        skip_whitespace(&mut chars);
+
+        match chars.next() {
+            Some(';') => nodes.push(vec![]),
+            Some(')') => return Ok(nodes),
+            Some('(') => return Err("variations are not supported".to_string()),
+            Some(c) if c.is_ascii_uppercase() => {
+                let mut id = c.to_string();
+                while let Some(&c) = chars.peek() && c.is_ascii_uppercase() {
+                    id.push(c);
+                    chars.next();
+                }
+
+                let mut values = vec![];
+                while skip_whitespace(&mut chars) && chars.next_if_eq(&'[').is_some() {
+                    values.push(read_value(&mut chars).ok_or(format!("{} has an unterminated value", id))?);
+                }
+
+                if values.is_empty() {
+                    return Err(format!("{} has no value", id));
+                }
+
+                nodes.last_mut().ok_or("properties before the first `;`")?.push((id, values));
+            }
+            Some(c) => return Err(format!("unexpected `{}`", c)),
+            None => return Err("the record ends without `)`".to_string()),
+        }
+    }
+}
+
+/// Always true, to chain in conditions.
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> bool {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    true
+}
+
+/// Reads up to the closing `]`, which is consumed, undoing `escape`.
+fn read_value(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut value = String::new();
+
+    loop {
+        match chars.next()? {
+            ']' => return Some(value),
+            '\\' => value.push(chars.next()?),
+            c => value.push(c),
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(']', "\\]")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{GameRecord, MoveStats, PlayerInfo};
+    use crate::{board::{BoardState, GameResult, Move, Player, Rules}, config::MctsConfig, win_detector::WinKind};
+
+    fn bridge_game() -> GameRecord {
+        let mut board = BoardState::with_rules(3, Rules { swap: true });
+        let mut record = GameRecord::new(&board, [PlayerInfo::human("Ann ] B\\"), PlayerInfo::engine("AI", &MctsConfig::default().seed(7))]);
+
+        // the second player takes the first stone over, then joins two corners along the top
+        let moves = ["c5", "swap", "a3", "d5", "b4", "e5"];
+        for (i, m) in moves.iter().enumerate() {
+            let m = board.apply_move(board.parse_move(m).unwrap()).unwrap();
+            let stats = MoveStats { playouts: Some(100 + i as u32), nodes: (i > 2).then_some(50), win_probability: Some(0.25) };
+            let stats = (i % 2 == 1).then_some(stats);
+            record.push(m, Duration::from_millis(1500), stats);
+        }
+
+        record
+    }
+
+    #[test]
+    fn records_read_back_as_written() {
+        let record = bridge_game();
+        let text = record.to_string();
+        let read = GameRecord::parse(&text).unwrap();
+
+        assert!(text.contains("RE[W+]WK[bridge]"), "{}", text);
+        assert_eq!((read.board_size, read.rules.swap), (3, true));
+        assert_eq!(read.players, record.players);
+        assert_eq!(read.moves, record.moves);
+        assert_eq!(read.final_board().win_info().map(|win| win.kind), Some(WinKind::Bridge));
+    }
+
+    #[test]
+    fn board_at_replays_the_first_moves() {
+        let record = bridge_game();
+
+        assert_eq!(record.board_at(0).ply(), 0);
+        assert_eq!(record.board_at(2).turn, Player::P1);
+        assert_eq!(record.board_at(2).moves().collect::<Vec<_>>(), [Move::Place(0, -2), Move::Swap]);
+        assert_eq!(record.board_at(5).result(), GameResult::Ongoing);
+        assert_eq!(record.board_at(100).result(), GameResult::Win(Player::P2));
+    }
+
+    #[test]
+    fn illegal_records_are_rejected() {
+        for text in [
+            "(;SZ[5];B[e5];B[e4])",
+            "(;SZ[5];B[e5];W[e5])",
+            "(;SZ[5];B[z9])",
+            "(;SZ[11])",
+            "(;SZ[5];B[e5]",
+            "(;SZ[5];B[e5](;W[e4]))",
+            "(;GM[hex]SZ[5])",
+            "(;SZ[5];B[e5]MT[soon])",
+            "(;SZ[5];B[e5]MT[inf])",
+            "(;SZ[5];B[e5]MT[1e30])",
+        ] {
+            assert!(GameRecord::parse(text).is_err(), "{} was accepted", text);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::{fmt, time::Duration};
+
+use crate::{board::{Move, Notation}, mcts::Proven};
+
+/// Root moves listed when a report is printed.
+const SHOWN_MOVES: usize = 5;
+
+/// What a search found and what it took, for frontends to display as analysis.
+#[derive(Debug, Clone)]
+pub struct SearchReport {
+    /// the move the configured `MoveSelection` picked
+    pub best_move: Move,
+    /// every root move, most visited first
+    pub moves: Vec<MoveReport>,
+    /// the best move and the most visited reply after each move from there on
+    pub principal_variation: Vec<Move>,
+    /// chance the side to move wins, from the best move's mean reward, exact once solved
+    pub win_probability: f32,
+    /// the root's game-theoretic value for the side to move, once the search has proven it
+    pub solved: Option<Proven>,
+    /// nodes in the tree, in every tree with root parallelism
+    pub nodes: usize,
+    /// nodes kept from the previous search
+    pub reused_nodes: usize,
+    /// visits the root already had from the previous search, in every tree with root parallelism
+    pub reused_visits: u32,
+    /// positions in the transposition table, when it is on
+    pub distinct_positions: Option<usize>,
+    pub playouts: u32,
+    pub elapsed: Duration,
+    /// board size and notation the moves are printed in
+    pub board_size: i8,
+    pub notation: Notation,
+}
+
+/// Statistics of one root move, from the point of view of the side to move.
+#[derive(Debug, Clone, Copy)]
+pub struct MoveReport {
+    pub mv: Move,
+    pub visits: u32,
+    /// average reward between -1 and 1
+    pub mean: f32,
+    /// the AMAF estimate RAVE ranks the move by before it has visits of its own, when RAVE is on
+    pub prior: Option<f32>,
+    pub proven: Option<Proven>,
+}
+
+impl SearchReport {
+    pub fn playouts_per_second(&self) -> f64 {
+        self.playouts as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn nodes_per_second(&self) -> f64 {
+        self.nodes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn format_move(&self, m: Move) -> String {
+        self.notation.format(m, self.board_size)
+    }
+}
+
+impl fmt::Display for SearchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.reused_nodes > 0 {
+            writeln!(f, "reused {} nodes with {} visits at the root", self.reused_nodes, self.reused_visits)?;
+        }
+
+        write!(f, "{} nodes, {} playouts in {:.2?} ({:.0}/s)", self.nodes, self.playouts, self.elapsed, self.playouts_per_second())?;
+        match self.distinct_positions {
+            Some(positions) => writeln!(f, ", {} distinct positions", positions)?,
+            None => writeln!(f)?,
+        }
+
+        for m in self.moves.iter().take(SHOWN_MOVES) {
+            write!(f, "  {:>10} {:>8} visits  mean {:+.3}", self.format_move(m.mv), m.visits, m.mean)?;
+            if let Some(prior) = m.prior {
+                write!(f, "  prior {:+.3}", prior)?;
+            }
+            match m.proven {
+                Some(Proven::Win) => writeln!(f, "  wins")?,
+                Some(Proven::Loss) => writeln!(f, "  loses")?,
+                None => writeln!(f)?,
+            }
+        }
+
+        let pv: Vec<String> = self.principal_variation.iter().map(|&m| self.format_move(m)).collect();
+        writeln!(f, "pv: {}", pv.join(" "))?;
+
+        match self.solved {
+            Some(Proven::Win) => writeln!(f, "solved: the side to move wins with {}", self.format_move(self.best_move)),
+            Some(Proven::Loss) => writeln!(f, "solved: the side to move loses, playing {}", self.format_move(self.best_move)),
+            None => writeln!(f, "best move {}, win probability {:.1}%", self.format_move(self.best_move), self.win_probability * 100.0),
+        }
+    }
+}
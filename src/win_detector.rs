@@ -1,250 +1,595 @@
-use std::{collections::{HashSet, VecDeque}, sync::Arc};
+use std::fmt;
 
-use crate::board::{self, BoardState, Hex, HexOwner, Player};
+use crate::{bitboard::{Bitboard, Geometry, MAX_CELLS}, board::{BoardState, HexOwner, Move, Notation, Player}};
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WinKind {
+    Ring,
+    Bridge,
+    Fork,
+}
+
+impl fmt::Display for WinKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WinKind::Ring => write!(f, "ring"),
+            WinKind::Bridge => write!(f, "bridge"),
+            WinKind::Fork => write!(f, "fork"),
+        }
+    }
+}
+
+/// The structure that won the game.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WinInfo {
+    pub player: Player,
+    pub kind: WinKind,
+    /// stones forming the structure: the loop for a ring, the whole connected group otherwise
+    pub cells: Vec<(i32, i32)>,
+    /// corners the group touches
+    pub corners: Vec<(i32, i32)>,
+    /// edge sides the group touches, numbered 0..6
+    pub sides: Vec<u8>,
+    /// board size and notation the corners are printed in
+    pub board_size: i8,
+    pub notation: Notation,
+}
+
+impl fmt::Display for WinInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} wins by {}", self.player, self.kind)?;
+
+        match self.kind {
+            WinKind::Ring => write!(f, " of {} stones", self.cells.len()),
+            WinKind::Bridge => {
+                let corners: Vec<String> = self.corners.iter()
+                    .map(|&(q, r)| self.notation.format(Move::Place(q, r), self.board_size))
+                    .collect();
+                write!(f, " between corners {}", corners.join(", "))
+            },
+            WinKind::Fork => {
+                let sides: Vec<String> = self.sides.iter().map(|s| s.to_string()).collect();
+                write!(f, " between sides {}", sides.join(", "))
+            },
+        }
+    }
+}
+
+/// Full-board win checker. `BoardState` tracks bridges and forks incrementally and only
+/// calls `ring_through` after a move and `ring_with` for its winning cells; the other checks
+/// are kept as a slow reference implementation.
 pub struct WinDetector<'a>{
     board: &'a BoardState,
-    corners: Vec<(i32, i32)>,
-    edges: Vec<(i32, i32)>
+    geometry: &'static Geometry,
 }
 
 impl<'a> WinDetector<'a> {
     pub fn from_board(board: &'a BoardState) -> Self {
-        let corners = Self::initialize_corners(board.board_size);
-        let edges = Self::initialize_edges(board.board_size);
-
-        Self { board, corners, edges }
+        Self { board, geometry: board.geometry() }
     }
 
-    pub fn run(&self, player: &Player) -> bool {
-        return self.check_bridge(player) || self.check_fork(player) || self.check_ring(player);
-    }
+    #[allow(dead_code)]
+    pub fn run(&self, player: &Player) -> Option<WinInfo> {
+        if let Some(cell) = self.check_bridge(player) {
+            return Some(self.connection_info(*player, cell, WinKind::Bridge));
+        }
 
-    // WANRING: can panic, there's no checking if a hex is out of bounds.
-    fn get_neighbours(&self, q: &i32, r: &i32) -> Vec<(i32, i32)> {
-        let q_val = *q;
-        let r_val = *r;
-        
-        let neighbours = vec![
-            (q_val + 1, r_val),
-            (q_val - 1, r_val),
-            (q_val, r_val + 1),
-            (q_val, r_val - 1),
-            (q_val + 1, r_val - 1),
-            (q_val - 1, r_val + 1)
-        ];
+        if let Some(cell) = self.check_fork(player) {
+            return Some(self.connection_info(*player, cell, WinKind::Fork));
+        }
 
-        neighbours
+        self.check_ring(player).map(|ring| self.ring_info(*player, &ring))
     }
 
-    fn initialize_corners(board_size: i8) -> Vec<(i32, i32)> {
+    /// Describes the bridge or fork made by the group containing `cell`.
+    pub fn connection_info(&self, player: Player, cell: usize, kind: WinKind) -> WinInfo {
+        let group = self.group_of(cell);
+        let (corners, sides) = self.find_connection(cell);
+
+        WinInfo {
+            player,
+            kind,
+            cells: self.to_coords(&group),
+            corners: group.iter()
+                .filter(|&c| corners & self.geometry.corner_bits[c] != 0)
+                .map(|c| self.geometry.coords[c])
+                .collect(),
+            sides: (0..6).filter(|side| sides & (1 << side) != 0).collect(),
+            board_size: self.board.board_size,
+            notation: self.board.notation,
+        }
+    }
 
-        let max_qr: i32 = (board_size) as i32 - 1;
+    pub fn ring_info(&self, player: Player, ring: &Bitboard) -> WinInfo {
+        WinInfo {
+            player,
+            kind: WinKind::Ring,
+            cells: self.to_coords(ring),
+            corners: vec![],
+            sides: vec![],
+            board_size: self.board.board_size,
+            notation: self.board.notation,
+        }
+    }
 
-        return vec![
-            (-max_qr, 0),
-            (-max_qr, max_qr),
-            (0, -max_qr),
-            (0, max_qr),
-            (max_qr, -max_qr),
-            (max_qr, 0)
-        ];        
+    fn to_coords(&self, cells: &Bitboard) -> Vec<(i32, i32)> {
+        cells.iter().map(|cell| self.geometry.coords[cell]).collect()
     }
 
-    fn initialize_edges(board_size: i8) -> Vec<(i32, i32)> {
-        let mut edges: Vec<(i32, i32)> = Vec::new();
-        let b = board_size as i32;
-        let r = b - 1;
+    /// Reference ring check: scans every cell for one that the player's stones close a loop around.
+    /// Returns the cells of the loop.
+    pub fn check_ring(&self, player: &Player) -> Option<Bitboard> {
+        let own = self.board.stones(*player);
 
-        // L, R
-        for i in 1..r { // skip corners
-            let left = (-r, i);
-            let right = (r, i - r);
-            edges.push(left);
-            edges.push(right);
+        for cell in self.geometry.all.difference(&self.geometry.boundary).iter() {
+            if let Some(ring) = self.enclosing_loop(cell, own) {
+                return Some(ring);
+            }
         }
 
-        // UR, LB
-        for i in 1..r { // skip corners
-            let ur = (i, -r);
-            let lb = (i - r, r);
-
-            edges.push(ur);
-            edges.push(lb);
-        }
+        None
+    }
 
-        // RB, UL
-        for i in 1..r { // skip corners
-            let rb = (i, r - i);
-            let ul = (i - r, -i);
+    /// Ring check after a stone was placed on `cell`: any new loop has to pass through `cell` and
+    /// enclose at least one of its neighbours, so only those need to be tested.
+    pub fn ring_through(&self, cell: usize) -> Option<Bitboard> {
+        let group = self.group_of(cell);
 
-            edges.push(rb);
-            edges.push(ul);
+        for n in self.geometry.neighbours_of(cell) {
+            if let Some(ring) = self.enclosing_loop(n, &group) {
+                return Some(ring);
+            }
         }
 
-        edges
+        None
     }
 
-    fn edge_side(&self, q: i32, r: i32) -> Option<u8> {
-        let n = self.board.board_size as i32 - 1;
-        let s = -q - r;
+    /// Whether a stone of `player` on the empty `cell` would close a ring, without placing it.
+    ///
+    /// `player` must not have a ring yet. Then any region their stones enclose once `cell` is
+    /// taken is new and bounded by the group `cell` joins, so every stone of theirs can stand
+    /// in as a wall without finding that group first. As in `ring_through`, each neighbour of
+    /// `cell` starts a region even when it is a stone of the group. The regions are filled on
+    /// a fixed stack, giving up on one as soon as it reaches the edge of the board or a region
+    /// known to reach it.
+    pub fn ring_with(&self, player: Player, cell: usize) -> bool {
+        let mut walls = *self.board.stones(player);
+        walls.insert(cell);
+
+        let mut leaked = Bitboard::default();
+        let mut stack = [0u16; MAX_CELLS];
+
+        for start in self.geometry.neighbours_of(cell) {
+            if leaked.contains(start) {
+                continue;
+            }
 
-        if q == -n { Some(0) }
-        else if r == -n { Some(1) }
-        else if s == -n { Some(2) }
-        else if q == n { Some(3) }
-        else if r == n { Some(4) }
-        else if s == n { Some(5) }
-        else { None }
-    }
+            let mut region = walls;
+            region.insert(start);
+            stack[0] = start as u16;
+            let mut len = 1;
+            let mut leaks = false;
+
+            while len > 0 && !leaks {
+                len -= 1;
+                let c = stack[len] as usize;
+                leaks = self.geometry.boundary.contains(c);
+
+                for n in self.geometry.neighbours_of(c) {
+                    leaks |= leaked.contains(n);
+
+                    if !region.contains(n) {
+                        region.insert(n);
+                        stack[len] = n as u16;
+                        len += 1;
+                    }
+                }
+            }
 
-    pub fn check_ring(&self, player: &Player) -> bool {
-        for ((_, _), hex) in &self.board.state {
-            let owner = hex.owner;
+            if !leaks {
+                return true;
+            }
 
-            if owner == HexOwner::None || owner != HexOwner::from(player) {
-                if !self.can_empty_cell_escape(hex.q, hex.r, player) {
-                    return true; 
-                }
+            // a stone as a start joins the regions on either side of it, which may not leak
+            // on their own
+            if !walls.contains(start) {
+                leaked = leaked.union(&region.difference(&walls));
             }
         }
 
         false
     }
 
-    fn can_empty_cell_escape(&self, start_q: i32, start_r: i32, player: &Player) -> bool {
-        let mut visited: HashSet<(i32, i32)> = HashSet::new();
-        let mut queue: VecDeque<(i32, i32)> = VecDeque::new();
-
-        visited.insert((start_q, start_r));
-        queue.push_back((start_q, start_r));
+    /// Loop of `walls` around `start`, if there is one.
+    ///
+    /// A ring encloses at least one cell of any owner, so `start` itself is never treated as a wall.
+    /// Flood-fills from `start` through every other non-wall cell; reaching a boundary cell means
+    /// the region leaks off the board. Otherwise the walls touching the region form the loop,
+    /// minus any dead-end spurs poking into it.
+    fn enclosing_loop(&self, start: usize, walls: &Bitboard) -> Option<Bitboard> {
+        let mut region = Bitboard::default();
+        let mut stack: Vec<usize> = vec![start];
+
+        region.insert(start);
+
+        while let Some(cell) = stack.pop() {
+            if self.geometry.boundary.contains(cell) {
+                return None;
+            }
 
-        while let Some((q, r)) = queue.pop_front() {
-            if self.is_on_board_boundary(q, r) {
-                return true;
+            for n in self.geometry.neighbours_of(cell) {
+                if !region.contains(n) && !walls.contains(n) {
+                    region.insert(n);
+                    stack.push(n);
+                }
             }
+        }
 
-            for (nq, nr) in self.get_neighbours(&q, &r) {
-                if visited.contains(&(nq, nr)) {
-                    continue;
+        let mut ring = Bitboard::default();
+        for cell in region.iter() {
+            for n in self.geometry.neighbours_of(cell) {
+                if n != start && walls.contains(n) {
+                    ring.insert(n);
                 }
+            }
+        }
+
+        loop {
+            let spurs: Vec<usize> = ring.iter()
+                .filter(|&cell| self.geometry.neighbours_of(cell).filter(|&n| ring.contains(n)).count() <= 1)
+                .collect();
 
-                let owner = self.get_hex_owner(&nq, &nr);
+            if spurs.is_empty() { break; }
 
-               
-                if owner != HexOwner::from(player) {
-                    visited.insert((nq, nr));
-                    queue.push_back((nq, nr));
+            for cell in spurs {
+                ring.remove(cell);
+            }
+        }
+
+        Some(ring)
+    }
+
+    /// Stones connected to `start`, including it.
+    fn group_of(&self, start: usize) -> Bitboard {
+        let own = match self.board.owner(start) {
+            HexOwner::P1 => self.board.stones(Player::P1),
+            HexOwner::P2 => self.board.stones(Player::P2),
+            HexOwner::None => return Bitboard::default(),
+        };
+
+        let mut group = Bitboard::default();
+        let mut stack: Vec<usize> = vec![start];
+
+        group.insert(start);
+
+        while let Some(cell) = stack.pop() {
+            for n in self.geometry.neighbours_of(cell) {
+                if !group.contains(n) && own.contains(n) {
+                    group.insert(n);
+                    stack.push(n);
                 }
             }
         }
 
-        false 
+        group
     }
 
-    fn is_on_board_boundary(&self, q: i32, r: i32) -> bool {
-        let s = -q - r;
-        let n = self.board.board_size as i32 - 1;
-        q.abs() == n || r.abs() == n || s.abs() == n
+    fn check_bridge(&self, player: &Player) -> Option<usize> {
+        self.board.stones(*player).iter().find(|&cell| {
+            let (corners, _) = self.find_connection(cell);
+            corners.count_ones() >= 2
+        })
     }
 
+    fn check_fork(&self, player: &Player) -> Option<usize> {
+        self.board.stones(*player).iter().find(|&cell| {
+            let (_, sides) = self.find_connection(cell);
+            sides.count_ones() >= 3
+        })
+    }
 
-    fn check_bridge(&self, player: &Player) -> bool {
-        for ((_, _), hex) in &self.board.state {
+    /// Corner and side bitmasks touched by the group containing `start`.
+    fn find_connection(&self, start: usize) -> (u8, u8) {
+        let mut corners_found: u8 = 0;
+        let mut sides_found: u8 = 0;
 
-            if hex.owner != HexOwner::from(player) { continue; };
+        for cell in self.group_of(start).iter() {
+            corners_found |= self.geometry.corner_bits[cell];
+            sides_found |= self.geometry.side_bits[cell];
+        }
 
-            let (corners, _) = self.find_connection(&hex.q, &hex.r, &player);
-            
-            if corners.len() >= 2 { return true };
-        };
+        (corners_found, sides_found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::IndexedRandom, SeedableRng};
+
+    use super::{WinDetector, WinKind};
+    use crate::board::{BoardState, GameResult, Move, Player};
+
+    /// Builds a position from a `print_state_pretty` diagram. Stones are placed row by row,
+    /// so a win reported by the board may come from any stone, not just the last one drawn.
+    fn board_from_diagram(diagram: &str) -> BoardState {
+        let rows: Vec<Vec<char>> = diagram
+            .lines()
+            .map(|line| line.split_whitespace().filter_map(|t| t.chars().next()).collect::<Vec<char>>())
+            .filter(|row| !row.is_empty())
+            .collect();
+
+        let board_size = rows.len().div_ceil(2) as i8;
+        let n = board_size as i32 - 1;
+        let mut board = BoardState::new(board_size);
+
+        for (i, row) in rows.iter().enumerate() {
+            let r = i as i32 - n;
+            let q_min = (-n).max(-r - n);
+
+            assert_eq!(row.len() as i32, n.min(-r + n) - q_min + 1, "row {} has the wrong length", i);
+
+            for (j, symbol) in row.iter().enumerate() {
+                let player = match symbol {
+                    'X' => Player::P1,
+                    'O' => Player::P2,
+                    _ => continue,
+                };
+
+                board.turn = player;
+                board.apply_move(Move::Place(q_min + j as i32, r)).unwrap();
+            }
+        }
+
+        board
+    }
 
-        return false;
+    fn ring_cells(board: &BoardState, player: Player) -> Option<Vec<(i32, i32)>> {
+        let ring = WinDetector::from_board(board).check_ring(&player)?;
+        let mut cells: Vec<(i32, i32)> = ring.iter().map(|cell| board.geometry().coords[cell]).collect();
+        cells.sort();
+        Some(cells)
     }
-    
-    fn check_fork(&self, player: &Player) -> bool {
-         for ((_, _), hex) in &self.board.state {
 
-            if hex.owner != HexOwner::from(player) { continue; };
+    fn assert_ring(diagram: &str, expected: &[(i32, i32)]) {
+        let board = board_from_diagram(diagram);
+        let mut expected = expected.to_vec();
+        expected.sort();
 
-            let (_, edges) = self.find_connection(&hex.q, &hex.r, &player);
-            
-            if edges.len() >= 3 { return true };
-        };
+        assert_eq!(ring_cells(&board, Player::P1), Some(expected.clone()));
+
+        let win = board.win_info().expect("incremental check missed the ring");
+        let mut cells = win.cells.clone();
+        cells.sort();
 
-        return false;
+        assert_eq!((win.player, win.kind, cells), (Player::P1, WinKind::Ring, expected));
     }
 
-    fn find_connection(&self, start_q: &i32, start_r: &i32, player: &Player) -> (HashSet<(i32, i32)>, HashSet<(i32, i32)>) {
-        let mut visited: HashSet<(i32, i32)> = HashSet::new();
-        let mut corners_found: HashSet<(i32, i32)> = HashSet::new();
-        // stores only 1 edge for a side
-        // TODO: rename to sides
-        let mut edges_found: HashSet<(i32, i32)> = HashSet::new();
+    fn assert_no_ring(diagram: &str) {
+        let board = board_from_diagram(diagram);
 
-        let mut queue: VecDeque<(i32, i32)> = VecDeque::from([(*start_q, *start_r)]);
+        assert_eq!(ring_cells(&board, Player::P1), None);
+        assert_eq!(board.result(), GameResult::Ongoing, "incremental check reported a win");
+    }
 
-        while queue.len() > 0 {
-            let (q, r) = queue.pop_front().unwrap();
+    const AROUND_ORIGIN: [(i32, i32); 6] = [(1, 0), (1, -1), (0, -1), (-1, 0), (-1, 1), (0, 1)];
+
+    #[test]
+    fn ring_around_empty_cell() {
+        assert_ring("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . X X . . .
+            . . . X . X . . .
+             . . . X X . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ", &AROUND_ORIGIN);
+    }
 
-            if self.is_corner(&q, &r) {
-                corners_found.insert((q, r));
-            }
+    #[test]
+    fn ring_around_opponent_stone() {
+        assert_ring("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . X X . . .
+            . . . X O X . . .
+             . . . X X . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ", &AROUND_ORIGIN);
+    }
 
-            if let Some(side) = self.edge_side(q, r) {
-                edges_found.insert((side as i32, 0));
-            }
+    #[test]
+    fn ring_around_own_stone() {
+        assert_ring("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . X X . . .
+            . . . X X X . . .
+             . . . X X . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ", &AROUND_ORIGIN);
+    }
 
-            let neighbours = self.get_neighbours(&q, &r);
+    #[test]
+    fn ring_around_two_cells() {
+        assert_ring("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . X X X . .
+            . . . X . O X . .
+             . . . X X X . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ", &[(0, -1), (1, -1), (2, -1), (-1, 0), (2, 0), (-1, 1), (0, 1), (1, 1)]);
+    }
 
-            for (qn, rn) in neighbours {
-                if visited.contains(&(qn, rn)) { continue; }
-                
-                
-                if (self.get_hex_owner(&qn, &rn) == HexOwner::from(player)) {
-                    visited.insert((qn, rn));
-                    queue.push_front((qn, rn));
-                }                
-            }
-        }
-        return (corners_found, edges_found);
+    #[test]
+    fn ring_along_the_edge() {
+        assert_ring("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . . . . X X
+            . . . . . . X . X
+             . . . . . . X X
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ", &[(3, -1), (4, -1), (2, 0), (4, 0), (2, 1), (3, 1)]);
     }
 
-    
+    #[test]
+    fn ring_ignores_stones_inside_it() {
+        assert_ring("
+                . . . . .
+               . . . . . .
+              . . . X X X .
+             . . . X . . X .
+            . . . X . X . X .
+             . . . X . . X .
+              . . . X X X .
+               . . . . . .
+                . . . . .
+        ", &[
+            (1, -2), (2, -2), (3, -2), (0, -1), (3, -1), (-1, 0),
+            (3, 0), (-1, 1), (2, 1), (-1, 2), (0, 2), (1, 2),
+        ]);
+    }
 
-    fn is_corner(&self, q: &i32, r: &i32) -> bool {
-        let mut is_corner: bool = false;
+    #[test]
+    fn open_horseshoe_is_not_a_ring() {
+        assert_no_ring("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . X X . . .
+            . . . X . X . . .
+             . . . X . . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ");
+    }
 
-        for corner in &self.corners {
-            if (*q, *r) == *corner {
-                is_corner = true;
-            }
-        };
+    #[test]
+    fn loop_broken_by_opponent_is_not_a_ring() {
+        assert_no_ring("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . X X . . .
+            . . . X . X . . .
+             . . . X O . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ");
+    }
 
-        return is_corner;
+    #[test]
+    fn solid_group_is_not_a_ring() {
+        assert_no_ring("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . . X . . .
+            . . . . X X . . .
+             . . . X X . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ");
     }
 
-    fn is_edge(&self, q: &i32, r: &i32) -> bool {
-        let mut is_edge: bool = false;
+    #[test]
+    fn walled_off_corner_is_not_a_ring() {
+        assert_no_ring("
+                . . X . .
+               . . . X . .
+              . . . . X X X
+             . . . . . . . .
+            . . . . . . . . .
+             . . . . . . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ");
+    }
 
-        for edge in &self.edges {
-            if (*q, *r) == *edge {
-                is_edge = true;
-            }
-        };
+    #[test]
+    fn corner_and_two_sides_are_not_a_fork() {
+        // a corner lies on two sides but counts as neither
+        let board = board_from_diagram("
+                . X . . .
+               . . X . . .
+              . . . X . . .
+             . . . . X X X X
+            X X X X X . . . .
+             . . . . . . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ");
+
+        assert_eq!(WinDetector::from_board(&board).run(&Player::P1), None);
+        assert_eq!(board.result(), GameResult::Ongoing, "incremental check reported a win");
+    }
 
-        return is_edge;
+    #[test]
+    fn opponent_ring_does_not_count() {
+        let board = board_from_diagram("
+                . . . . .
+               . . . . . .
+              . . . . . . .
+             . . . O O . . .
+            . . . O X O . . .
+             . . . O O . . .
+              . . . . . . .
+               . . . . . .
+                . . . . .
+        ");
+
+        assert_eq!(ring_cells(&board, Player::P1), None);
+        assert_eq!(ring_cells(&board, Player::P2), Some({
+            let mut cells = AROUND_ORIGIN.to_vec();
+            cells.sort();
+            cells
+        }));
+        assert_eq!(board.result(), GameResult::Win(Player::P2));
     }
 
-    fn get_hex_owner(&self, q: &i32, r: &i32) -> HexOwner {
-            if !self.board.is_hex_in_bounds(*q, *r) {
-            return HexOwner::None;
+    #[test]
+    fn incremental_result_matches_the_full_check_in_random_games() {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        for board_size in [2, 3, 4, 5, 6, 8] {
+            for _ in 0..10 {
+                let mut board = BoardState::new(board_size);
+
+                while !board.is_terminal() {
+                    let m = *board.legal_moves().choose(&mut rng).unwrap();
+                    board.apply_move(m).unwrap();
+
+                    let detector = WinDetector::from_board(&board);
+                    for player in [Player::P1, Player::P2] {
+                        assert_eq!(
+                            board.result() == GameResult::Win(player),
+                            detector.run(&player).is_some(),
+                            "{:?} after {:?} on size {}:\n{}", player, board.moves().collect::<Vec<_>>(), board_size, board,
+                        );
+                    }
+                }
+            }
         }
-
-        self.board
-            .state
-            .get(&(*q, *r))
-            .map(|h| h.owner)
-            .unwrap_or(HexOwner::None)
     }
-}
\ No newline at end of file
+}